@@ -1,27 +1,238 @@
+use std::env;
+use std::error::Error;
+use std::fs;
 use std::io;
+use std::process;
 
-mod parsemath;
-use parsemath::parser::{Parser, ParseErr};
-use parsemath::ast;
+use rcalc::parsemath::parser::{self, Statement};
+use rcalc::parsemath::ast;
+use rcalc::parsemath::calc::{self, TableRange};
+use rcalc::parsemath::context::Context;
+use rcalc::parsemath::defs;
+use rcalc::parsemath::format::{self, format_with, FormatOpts};
+use rcalc::parsemath::render::pretty_tree;
+#[cfg(feature = "session")]
+use rcalc::parsemath::session;
 
+const USAGE: &str = "Usage: rcalc [--ast | --verbose] [--precision N] [--defs PATH] [--load PATH] [--file PATH] [EXPRESSION...]
+       rcalc --table EXPRESSION [--var NAME] --from N --to N [--step N]";
+
+/// Command line options
+#[derive(Default)]
+struct Options {
+    verbose: bool,
+    /// Decimal places shown, overriding RCALC_PRECISION
+    precision: Option<usize>,
+    /// Definitions of constants and functions to add before evaluating
+    defs: Option<String>,
+    /// Session file with variables to restore before evaluating
+    #[cfg(feature = "session")]
+    load: Option<String>,
+    file: Option<String>,
+    /// Expression to print a table of, over the range of `var`
+    table: Option<String>,
+    var: Option<String>,
+    from: Option<f64>,
+    to: Option<f64>,
+    step: Option<f64>,
+    exprs: Vec<String>,
+}
 
 fn main() {
+    let options = match parse_args(env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n{}", error, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let opts = match FormatOpts::from_env() {
+        Ok(opts) => opts,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            process::exit(2);
+        }
+    };
+    let opts = match options.precision {
+        Some(precision) => opts.with_precision(precision),
+        None => opts,
+    };
+
+    let mut ctx = Context::new();
+    if let Some(path) = &options.defs {
+        if let Err(error) = defs::load_definitions(&mut ctx, path) {
+            eprintln!("Error: can not load definitions {}: {}", path, error);
+            process::exit(1);
+        }
+    }
+    #[cfg(feature = "session")]
+    if let Some(path) = &options.load {
+        if let Err(error) = session::load_vars(&mut ctx, path) {
+            eprintln!("Error: can not load {}: {}", path, error);
+            process::exit(1);
+        }
+    }
+    if let Some(expr) = &options.table {
+        process::exit(run_table(expr, &options, &mut ctx, &opts));
+    }
+    if let Some(path) = &options.file {
+        process::exit(run_file(path, &mut ctx, &opts));
+    }
+    if options.exprs.is_empty() {
+        repl(&options, &mut ctx, &opts);
+        return;
+    }
+
+    for expr in &options.exprs {
+        match evaluate(expr, &mut ctx, options.verbose) {
+            Ok(Some(val)) => println!("{}", format_with(val, &opts)),
+            Ok(None) => {}
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                process::exit(1);
+            }
+        }
+    }
+}
+
+/// Arguments not starting with `--` are expressions to evaluate instead of starting the REPL
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ast" | "--verbose" => options.verbose = true,
+            "--precision" => {
+                let value = args.next().ok_or("Missing number after --precision")?;
+                options.precision = Some(format::parse_precision(&value).map_err(|e| e.to_string())?);
+            }
+            "--defs" => options.defs = Some(args.next().ok_or("Missing path after --defs")?),
+            #[cfg(feature = "session")]
+            "--load" => options.load = Some(args.next().ok_or("Missing path after --load")?),
+            "--file" => options.file = Some(args.next().ok_or("Missing path after --file")?),
+            "--table" => options.table = Some(args.next().ok_or("Missing expression after --table")?),
+            "--var" => options.var = Some(args.next().ok_or("Missing name after --var")?),
+            "--from" => options.from = Some(parse_number(&arg, args.next())?),
+            "--to" => options.to = Some(parse_number(&arg, args.next())?),
+            "--step" => options.step = Some(parse_number(&arg, args.next())?),
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+            _ => options.exprs.push(arg),
+        }
+    }
+    if options.table.is_some() && (options.from.is_none() || options.to.is_none()) {
+        return Err("--table needs --from and --to".into());
+    }
+    Ok(options)
+}
+
+fn parse_number(flag: &str, value: Option<String>) -> Result<f64, String> {
+    let value = value.ok_or(format!("Missing number after {}", flag))?;
+    value.parse().map_err(|_| format!("Invalid number {} after {}", value, flag))
+}
+
+/// Prints the values of the variable and the expression in two aligned columns,
+/// showing errors in the row they occur. Returns the process exit code.
+fn run_table(expr: &str, options: &Options, ctx: &mut Context, opts: &FormatOpts) -> i32 {
+    let var = options.var.as_deref().unwrap_or("x");
+    let range = TableRange {
+        from: options.from.unwrap_or_default(),
+        to: options.to.unwrap_or_default(),
+        step: options.step.unwrap_or(1.0),
+    };
+    let rows = match calc::table(expr, var, range, ctx) {
+        Ok(rows) => rows,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            return 1;
+        }
+    };
+
+    let rows: Vec<(String, String)> = rows.into_iter()
+        .map(|(value, result)| (format_with(value, opts), match result {
+            Ok(result) => format_with(result, opts),
+            Err(error) => format!("Error: {}", error),
+        }))
+        .collect();
+    let width = rows.iter()
+        .map(|(value, _)| value.len())
+        .chain([var.len()])
+        .max()
+        .unwrap_or_default();
+    println!("{:>width$}  {}", var, expr.trim(), width = width);
+    for (value, result) in rows {
+        println!("{:>width$}  {}", value, result, width = width);
+    }
+    0
+}
+
+/// Evaluates each line of the file, reporting failing lines without stopping.
+/// Returns the process exit code.
+fn run_file(path: &str, ctx: &mut Context, opts: &FormatOpts) -> i32 {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) => {
+            eprintln!("Error: can not read {}: {}", path, error);
+            return 1;
+        }
+    };
+
+    let mut exit_code = 0;
+    for (line_no, result) in calc::eval_lines(contents.lines(), ctx) {
+        match result {
+            Ok(val) => println!("{}", format_with(val, opts)),
+            Err(error) => {
+                eprintln!("Error on line {}: {}", line_no, error);
+                exit_code = 1;
+            }
+        }
+    }
+    exit_code
+}
+
+fn repl(options: &Options, ctx: &mut Context, opts: &FormatOpts) {
     println!("Hello! Welcome to Arithmetic expression evaluator.");
     println!("You can calculate value for expression such as 2*3+(4-5)+2^3/4. ");
     println!("Allowed numbers: positive, negative and decimals.");
     println!("Supported operations: Add, Subtract, Multiply, Divide, PowerOf(^). ");
+    println!("Variables can be assigned with x = 2 and statements separated by ';'.");
+    println!("Functions can be defined with f(x) = x^2 + 1.");
+    println!("Functions such as sqrt(x), sin(x), max(a, b) and constants pi, e are available.");
+    println!("Type :clear to forget variables or :reset to restore the defaults.");
+    #[cfg(feature = "session")]
+    println!("Type :save PATH to store variables in a file and :load PATH to restore them.");
     println!("Enter your arithmetic expression below:");
 
     loop {
         let mut input = String::new();
         match io::stdin().read_line(&mut input) {
+            Ok(0) => break,
+            Ok(_) if input.trim() == ":clear" => ctx.clear_vars(),
+            Ok(_) if input.trim() == ":reset" => ctx.clear_all(),
+            #[cfg(feature = "session")]
+            Ok(_) if input.trim().starts_with(":save ") => {
+                let path = input.trim()[":save ".len()..].trim();
+                match session::save_vars(ctx, path) {
+                    Ok(()) => println!("Saved variables to {}\n", path),
+                    Err(error) => println!("Error: can not save {}: {}\n", path, error),
+                }
+            }
+            #[cfg(feature = "session")]
+            Ok(_) if input.trim().starts_with(":load ") => {
+                let path = input.trim()[":load ".len()..].trim();
+                match session::load_vars(ctx, path) {
+                    Ok(count) => println!("Loaded {} variable(s) from {}\n", count, path),
+                    Err(error) => println!("Error: can not load {}: {}\n", path, error),
+                }
+            }
             Ok(_) => {
-                match evaluate(input) {
-                    Ok(val) => println!("The computed number is {}\n", val),
-                    Err(_) => {
+                match evaluate(&input, ctx, options.verbose) {
+                    Ok(Some(val)) => println!("The computed number is {}\n", format_with(val, opts)),
+                    Ok(None) => {}
+                    Err(error) => {
                         println!("
-                            Error in evaluating expression. 
-                            Please enter valid expression.\n"
+                            Error in evaluating expression: {}
+                            Please enter valid expression.\n",
+                            error
                         );
                     }
                 }
@@ -31,11 +242,20 @@ fn main() {
     }
 }
 
-fn evaluate(expr: String) -> Result<f64, ParseErr> {
-    let expr = expr.split_whitespace().collect::<String>();
-    let mut math_parser = Parser::new(&expr)?;
-    let ast = math_parser.parse()?;
-    println!("The generated AST is {:?}", ast);
+/// Evaluates all statements in the line and returns the value of the last one
+fn evaluate(expr: &str, ctx: &mut Context, verbose: bool) -> Result<Option<f64>, Box<dyn Error>> {
+    let program = parser::parse_statements(expr)?;
+    if verbose {
+        for statement in &program {
+            match statement {
+                Statement::Expr(expr) => print!("{}", pretty_tree(expr)),
+                Statement::Function { name, params, body } => {
+                    print!("Function {}({})\n{}", name, params.join(", "), pretty_tree(body));
+                }
+            }
+        }
+    }
 
-    Ok(ast::eval(ast)?)
+    let values = ast::eval_statements(&program, ctx)?;
+    Ok(values.last().copied())
 }
\ No newline at end of file
@@ -1,7 +1,14 @@
-/// This module contains list of valid AST nodes
+//! This module contains list of valid AST nodes
 use std::error;
+use std::fmt;
+use std::hash::{Hash, Hasher};
 
-#[derive(Clone, Debug)]
+use super::context::{Arity, Context};
+use super::defs;
+use super::format::format_result;
+use super::parser::Statement;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Node {
     Add(Box<Node>, Box<Node>),
     Subtract(Box<Node>, Box<Node>),
@@ -9,29 +16,542 @@ pub enum Node {
     Divide(Box<Node>, Box<Node>),
     Caret(Box<Node>, Box<Node>),
     Negative(Box<Node>),
+    Call(String, Vec<Node>),
+    Variable(String),
+    Assign(String, Box<Node>),
+    /// Equality test, evaluating to 1 when both sides are equal and 0 otherwise
+    Equal(Box<Node>, Box<Node>),
+    /// Inequality test, evaluating to 0 when both sides are equal and 1 otherwise
+    NotEqual(Box<Node>, Box<Node>),
+    /// Percent change `from X to Y`, that is `(Y - X) / X * 100`
+    PercentChange(Box<Node>, Box<Node>),
     Number(f64),
 }
 
+/// Hashes the structure of the tree, so that equal nodes hash the same.
+/// Numbers hash by their bits, with `-0` hashed as `0` since both compare equal.
+impl Hash for Node {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        use self::Node::*;
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Add(left, right) | Subtract(left, right) | Multiply(left, right)
+            | Divide(left, right) | Caret(left, right) | Equal(left, right)
+            | NotEqual(left, right) | PercentChange(left, right) => {
+                left.hash(state);
+                right.hash(state);
+            }
+            Negative(expr) => expr.hash(state),
+            Call(name, args) => {
+                name.hash(state);
+                args.hash(state);
+            }
+            Variable(name) => name.hash(state),
+            Assign(name, expr) => {
+                name.hash(state);
+                expr.hash(state);
+            }
+            Number(n) => (if *n == 0.0 { 0.0 } else { *n }).to_bits().hash(state),
+        }
+    }
+}
+
+/// Evaluates the AST without any variables defined
 pub fn eval(expr: Node) -> Result<f64, Box<dyn error::Error>> {
+    Ok(eval_with(&expr, &mut Context::new())?)
+}
+
+/// Evaluates the AST against the given context. Assignments update the context and return the assigned value.
+///
+/// Evaluation order is guaranteed to be left to right: the left operand of a binary operator is
+/// evaluated before the right one, and function arguments are evaluated in the order written,
+/// all before the function itself is called. Side effects of assignments and stateful functions
+/// therefore happen in source order, e.g. `f(1) - f(2)` calls `f(1)` first.
+pub fn eval_with(expr: &Node, ctx: &mut Context) -> Result<f64, EvalError> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::trace_span!("eval").entered();
+    #[cfg(feature = "tracing")]
+    let start = std::time::Instant::now();
+    let result = eval_node(expr, ctx);
+    #[cfg(feature = "tracing")]
+    tracing::trace!(node = %expr, result = ?result, elapsed_ns = start.elapsed().as_nanos() as u64, "evaluated");
+    result
+}
+
+fn eval_node(expr: &Node, ctx: &mut Context) -> Result<f64, EvalError> {
     use self::Node::*;
     match expr {
-        Number(i) => Ok(i),
-        Add(expr1, expr2) => Ok(
-            eval(*expr1)? + eval(*expr2)?
-        ),
-        Subtract(expr1, expr2) => Ok(
-            eval(*expr1)? - eval(*expr2)?
-        ),
-        Multiply(expr1, expr2) => Ok(
-            eval(*expr1)? * eval(*expr2)?
-        ),
-        Divide(expr1, expr2) => Ok(
-            eval(*expr1)? / eval(*expr2)?
-        ),
-        Negative(expr1) => Ok(-(eval(*expr1)?)),
-        Caret(expr1, expr2) => Ok(
-            eval(*expr1)?
-                .powf(eval(*expr2)?)
-        ),
-    }
-}
\ No newline at end of file
+        Number(i) => Ok(*i * ctx.input_scale_factor()),
+        Caret(expr1, expr2) => {
+            let base = eval_with(expr1, ctx)?;
+            let exponent = ctx.unscaled(|ctx| eval_with(expr2, ctx))?;
+            apply(expr, &[base, exponent], ctx)
+        }
+        Add(expr1, expr2) | Subtract(expr1, expr2) | Multiply(expr1, expr2)
+        | Divide(expr1, expr2) | Equal(expr1, expr2) | NotEqual(expr1, expr2)
+        | PercentChange(expr1, expr2) => {
+            let left = eval_with(expr1, ctx)?;
+            let right = eval_with(expr2, ctx)?;
+            apply(expr, &[left, right], ctx)
+        }
+        Negative(expr1) => {
+            let operand = eval_with(expr1, ctx)?;
+            apply(expr, &[operand], ctx)
+        }
+        Call(name, args) => {
+            let func = ctx.get_func(name)
+                .cloned()
+                .ok_or_else(|| EvalError::UnknownFunction(name.clone()))?;
+            let args = args.iter()
+                .map(|arg| eval_with(arg, ctx))
+                .collect::<Result<Vec<f64>, EvalError>>()?;
+            ctx.call_in_scope(name, &func, &args)
+        }
+        Variable(name) => ctx.lookup(name)
+            .ok_or_else(|| EvalError::UnboundVariable(name.clone())),
+        Assign(name, expr1) => {
+            let value = eval_with(expr1, ctx)?;
+            ctx.set_var(name, value)?;
+            Ok(value)
+        }
+    }
+}
+
+/// Applies the operation of the node to the values of its children, which are not evaluated
+/// again. Evaluators computing the operands their own way share the operators this way
+/// without wrapping values into number literals, which the input scale would scale again.
+pub(crate) fn apply(node: &Node, operands: &[f64], ctx: &mut Context) -> Result<f64, EvalError> {
+    use self::Node::*;
+    match node {
+        Add(..) => {
+            let (left, right) = (operands[0], operands[1]);
+            let sum = left + right;
+            if ctx.precision_warnings_enabled() && loses_operand(left, right, sum) {
+                ctx.warn(Warning::PrecisionLoss);
+            }
+            Ok(sum)
+        }
+        Subtract(..) => Ok(operands[0] - operands[1]),
+        Multiply(..) => Ok(operands[0] * operands[1]),
+        Divide(..) => Ok(operands[0] / operands[1]),
+        Caret(..) => power(operands[0], operands[1]),
+        Equal(..) => Ok(if ctx.approx_eq(operands[0], operands[1]) { 1.0 } else { 0.0 }),
+        NotEqual(..) => Ok(if ctx.approx_eq(operands[0], operands[1]) { 0.0 } else { 1.0 }),
+        PercentChange(..) => percent_change(operands[0], operands[1]),
+        Negative(_) => Ok(-operands[0]),
+        Call(name, _) => {
+            let func = ctx.get_func(name)
+                .cloned()
+                .ok_or_else(|| EvalError::UnknownFunction(name.clone()))?;
+            ctx.call_in_scope(name, &func, operands)
+        }
+        Assign(name, _) => {
+            ctx.set_var(name, operands[0])?;
+            Ok(operands[0])
+        }
+        Number(_) | Variable(_) => eval_node(node, ctx),
+    }
+}
+
+/// Evaluates whatever can be evaluated and keeps the rest symbolic, e.g. `x + 2*3` is `x + 6`
+/// when `x` is unknown. Unknown identifiers are errors unless the context has `auto_vars` on,
+/// in which case they stay free variables. Assignments of symbolic values are kept unevaluated.
+/// Numbers of the result are final values, with literals already scaled by the input scale.
+pub fn partial_eval(expr: &Node, ctx: &mut Context) -> Result<Node, EvalError> {
+    use self::Node::*;
+    let binary = |left: &Node, right: &Node, ctx: &mut Context, build: fn(Box<Node>, Box<Node>) -> Node| {
+        let left = partial_eval(left, ctx)?;
+        let right = partial_eval(right, ctx)?;
+        let node = build(Box::new(left), Box::new(right));
+        fold(node, ctx)
+    };
+    match expr {
+        Number(n) => Ok(Number(*n * ctx.input_scale_factor())),
+        Variable(name) => match ctx.lookup(name) {
+            Some(value) => Ok(Number(value)),
+            None if ctx.auto_vars_enabled() => Ok(expr.clone()),
+            None => Err(EvalError::UnboundVariable(name.clone())),
+        },
+        Add(left, right) => binary(left, right, ctx, Add),
+        Subtract(left, right) => binary(left, right, ctx, Subtract),
+        Multiply(left, right) => binary(left, right, ctx, Multiply),
+        Divide(left, right) => binary(left, right, ctx, Divide),
+        Caret(base, exponent) => {
+            let base = partial_eval(base, ctx)?;
+            let exponent = ctx.unscaled(|ctx| partial_eval(exponent, ctx))?;
+            fold(Caret(Box::new(base), Box::new(exponent)), ctx)
+        }
+        Equal(left, right) => binary(left, right, ctx, Equal),
+        NotEqual(left, right) => binary(left, right, ctx, NotEqual),
+        PercentChange(from, to) => binary(from, to, ctx, PercentChange),
+        Negative(expr) => {
+            let node = Negative(Box::new(partial_eval(expr, ctx)?));
+            fold(node, ctx)
+        }
+        Call(name, args) => {
+            let args = args.iter()
+                .map(|arg| partial_eval(arg, ctx))
+                .collect::<Result<Vec<Node>, EvalError>>()?;
+            fold(Call(name.clone(), args), ctx)
+        }
+        Assign(name, expr) => match partial_eval(expr, ctx)? {
+            Number(value) => {
+                ctx.set_var(name, value)?;
+                Ok(Number(value))
+            }
+            expr => Ok(Assign(name.clone(), Box::new(expr))),
+        },
+    }
+}
+
+/// Applies the operation of the node if its operands are all numbers, keeping it otherwise
+fn fold(node: Node, ctx: &mut Context) -> Result<Node, EvalError> {
+    use self::Node::*;
+    let operands = match &node {
+        Add(left, right) | Subtract(left, right) | Multiply(left, right)
+        | Divide(left, right) | Caret(left, right) | Equal(left, right)
+        | NotEqual(left, right) | PercentChange(left, right) => {
+            match (&**left, &**right) {
+                (Number(left), Number(right)) => Some(vec![*left, *right]),
+                _ => None,
+            }
+        }
+        Negative(expr) => match **expr {
+            Number(operand) => Some(vec![operand]),
+            _ => None,
+        },
+        Call(_, args) => args.iter()
+            .map(|arg| match arg {
+                Number(value) => Some(*value),
+                _ => None,
+            })
+            .collect(),
+        Number(_) | Variable(_) | Assign(..) => None,
+    };
+    match operands {
+        Some(operands) => Ok(Number(apply(&node, &operands, ctx)?)),
+        None => Ok(node),
+    }
+}
+
+/// Evaluates every statement of a program in order, sharing the context between them.
+/// The value of each statement is stored as `ans`.
+pub fn eval_program(exprs: &[Node], ctx: &mut Context) -> Result<Vec<f64>, EvalError> {
+    exprs.iter()
+        .map(|expr| {
+            let value = eval_with(expr, ctx)?;
+            ctx.set_ans(value);
+            Ok(value)
+        })
+        .collect()
+}
+
+/// Evaluates statements like `eval_program`. Function definitions are registered in the
+/// context and have no value. The value of each expression is stored as `ans` and returned.
+pub fn eval_statements(statements: &[Statement], ctx: &mut Context) -> Result<Vec<f64>, EvalError> {
+    let mut values = Vec::new();
+    for statement in statements {
+        match statement {
+            Statement::Expr(expr) => {
+                let value = eval_with(expr, ctx)?;
+                ctx.set_ans(value);
+                values.push(value);
+            }
+            Statement::Function { name, params, body } => {
+                defs::define_function(ctx, name, params.clone(), body.clone());
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Raises base to the exponent. By convention `0^0 == 1`, while a zero base
+/// with a negative exponent is a division by zero.
+pub(crate) fn power(base: f64, exponent: f64) -> Result<f64, EvalError> {
+    if base == 0.0 && exponent < 0.0 {
+        return Err(EvalError::DivisionByZero);
+    }
+    if base == 0.0 && exponent == 0.0 {
+        return Ok(1.0);
+    }
+    Ok(base.powf(exponent))
+}
+
+/// Change from one value to another in percent, undefined when starting from zero
+pub(crate) fn percent_change(from: f64, to: f64) -> Result<f64, EvalError> {
+    if from == 0.0 {
+        return Err(EvalError::InvalidArgument("percent change from 0 is undefined".into()));
+    }
+    Ok((to - from) / from * 100.0)
+}
+
+/// Checks whether a finite addition swallowed one of its nonzero operands
+fn loses_operand(left: f64, right: f64, sum: f64) -> bool {
+    sum.is_finite() && ((right != 0.0 && sum == left) || (left != 0.0 && sum == right))
+}
+
+/// Non fatal problems noticed during evaluation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    PrecisionLoss,
+    /// Top-level assignment to the variable where a comparison was expected
+    AssignmentInCondition(String),
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            self::Warning::PrecisionLoss => write!(f, "precision loss in addition"),
+            self::Warning::AssignmentInCondition(name) => write!(f,
+                "assignment to {} in condition, did you mean '=='?", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EvalError {
+    UnboundVariable(String),
+    UnknownFunction(String),
+    ConstantAssignment(String),
+    ArgumentCount { function: String, expected: Arity, got: usize },
+    DivisionByZero,
+    InvalidArgument(String),
+    IncompatibleUnits(String, String),
+    DomainError { function: String, value: f64, requirement: String },
+    /// Argument above the configured limit of the function
+    OutOfRange { function: String, value: f64, limit: f64 },
+    /// Value of the wrong kind, such as a boolean in arithmetic
+    TypeError(String),
+    /// Function returned NaN while the NaN policy is to fail
+    NotANumber(String),
+    /// Function calls nested deeper than the recursion limit of the context
+    RecursionLimit(String),
+    /// Integer operation outside of i64 while checked integers are enabled
+    Overflow(String),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            self::EvalError::UnboundVariable(name) => write!(f,
+                "Unbound variable {}", name),
+            self::EvalError::UnknownFunction(name) => write!(f,
+                "Unknown function {}", name),
+            self::EvalError::ConstantAssignment(name) => write!(f,
+                "Can not assign to constant {}", name),
+            self::EvalError::ArgumentCount { function, expected, got } => write!(f,
+                "Function {} expects {} argument(s), got {}", function, expected, got),
+            self::EvalError::DivisionByZero => write!(f, "Division by zero"),
+            self::EvalError::InvalidArgument(e) => write!(f, "Invalid argument: {}", e),
+            self::EvalError::IncompatibleUnits(from, to) => write!(f,
+                "Incompatible units: {} and {}", from, to),
+            self::EvalError::DomainError { function, value, requirement } => write!(f,
+                "{} domain error: argument {} {}", function, format_result(*value), requirement),
+            self::EvalError::OutOfRange { function, value, limit } => write!(f,
+                "{} argument {} exceeds the limit of {}", function, format_result(*value), format_result(*limit)),
+            self::EvalError::TypeError(e) => write!(f, "Type error: {}", e),
+            self::EvalError::NotANumber(name) => write!(f, "Function {} returned NaN", name),
+            self::EvalError::RecursionLimit(name) => write!(f,
+                "Function {} exceeded the recursion limit", name),
+            self::EvalError::Overflow(operation) => write!(f, "Integer overflow in {}", operation),
+        }
+    }
+}
+
+impl error::Error for EvalError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::{self, Parser};
+    use super::super::tokenizer::Tokenizer;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn eval_str(expr: &str, ctx: &mut Context) -> Result<f64, EvalError> {
+        eval_with(&Parser::new(expr).unwrap().parse().unwrap(), ctx)
+    }
+
+    #[test]
+    fn program_shares_the_context() {
+        let program = parser::parse_program("a = 2; a + 1").unwrap();
+        let mut ctx = Context::new();
+        assert_eq!(eval_program(&program, &mut ctx), Ok(vec![2.0, 3.0]));
+        assert_eq!(ctx.get_var("a"), Some(2.0));
+    }
+
+    fn eval_statements_str(expr: &str, ctx: &mut Context) -> Result<Vec<f64>, EvalError> {
+        eval_statements(&parser::parse_statements(expr).unwrap(), ctx)
+    }
+
+    #[test]
+    fn defined_functions_take_their_arguments() {
+        let mut ctx = Context::new();
+        assert_eq!(eval_statements_str("f(x) = x^2 + 1; f(3)", &mut ctx), Ok(vec![10.0]));
+        assert_eq!(eval_statements_str("h(a, b) = a - b; h(3, 1)", &mut ctx), Ok(vec![2.0]));
+        assert_eq!(ctx.ans(), Some(2.0));
+        assert!(matches!(eval_str("h(1)", &mut ctx), Err(EvalError::ArgumentCount { .. })));
+    }
+
+    #[test]
+    fn defined_functions_are_evaluated_when_called() {
+        let mut ctx = Context::new();
+        let values = eval_statements_str("a = 1; f(x) = x + a; a = 2; f(0)", &mut ctx);
+        assert_eq!(values, Ok(vec![1.0, 2.0, 2.0]));
+        // Functions defined later can be called
+        assert_eq!(eval_statements_str("f(x) = g(x); g(x) = 2*x; f(3)", &mut ctx), Ok(vec![6.0]));
+        // Recursion is bounded
+        eval_statements_str("loop(n) = loop(n + 1)", &mut ctx).unwrap();
+        assert_eq!(eval_str("loop(0)", &mut ctx), Err(EvalError::RecursionLimit("loop".into())));
+    }
+
+    #[test]
+    fn parameters_are_local_to_the_call() {
+        let mut ctx = Context::new().with_var("x", 5.0);
+        assert_eq!(eval_statements_str("f(x) = x*2; f(1)", &mut ctx), Ok(vec![2.0]));
+        assert_eq!(eval_statements_str("g(pi) = pi + 1; g(1)", &mut ctx), Ok(vec![2.0]));
+        assert_eq!(eval_statements_str("k(y) = (t = y + 1) * t; k(2)", &mut ctx), Ok(vec![9.0]));
+        assert_eq!((ctx.get_var("x"), ctx.get_var("t")), (Some(5.0), None));
+    }
+
+    #[test]
+    fn warnings_from_function_bodies_are_kept() {
+        let mut ctx = Context::new().precision_warnings(true);
+        assert_eq!(eval_statements_str("f(x) = x + 1; f(1e20)", &mut ctx), Ok(vec![1e20]));
+        assert_eq!(ctx.take_warnings(), vec![Warning::PrecisionLoss]);
+    }
+
+    #[test]
+    fn warns_when_addition_loses_an_operand() {
+        let mut ctx = Context::new().precision_warnings(true);
+        assert_eq!(eval_str("1e20 + 1", &mut ctx), Ok(1e20));
+        assert_eq!(ctx.take_warnings(), vec![Warning::PrecisionLoss]);
+        assert_eq!(eval_str("1e10 + 1", &mut ctx), Ok(1e10 + 1.0));
+        assert!(ctx.take_warnings().is_empty());
+
+        let mut quiet = Context::new();
+        eval_str("1e20 + 1", &mut quiet).unwrap();
+        assert!(quiet.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn zero_base_powers() {
+        let mut ctx = Context::new();
+        assert_eq!(eval_str("0^0", &mut ctx), Ok(1.0));
+        assert_eq!(eval_str("0^2", &mut ctx), Ok(0.0));
+        assert_eq!(eval_str("0^-1", &mut ctx), Err(EvalError::DivisionByZero));
+    }
+
+    /// Context with `f(x)` recording its argument and returning the number of calls so far
+    fn counting_context() -> (Context, Rc<RefCell<Vec<f64>>>) {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = Rc::clone(&calls);
+        let ctx = Context::new().with_func("f", Arity::Exact(1), move |args| {
+            recorded.borrow_mut().push(args[0]);
+            Ok(recorded.borrow().len() as f64)
+        });
+        (ctx, calls)
+    }
+
+    #[test]
+    fn operands_are_evaluated_left_to_right() {
+        let (mut ctx, calls) = counting_context();
+        assert_eq!(eval_str("f(1) - f(2)", &mut ctx), Ok(1.0 - 2.0));
+        assert_eq!(*calls.borrow(), vec![1.0, 2.0]);
+        assert_eq!(eval_str("(x = 1) + (x = 2) * x", &mut ctx), Ok(5.0));
+    }
+
+    #[test]
+    fn arguments_are_evaluated_left_to_right() {
+        let (mut ctx, calls) = counting_context();
+        assert_eq!(eval_str("max(f(1), f(2), f(3)) + f(4)", &mut ctx), Ok(3.0 + 4.0));
+        assert_eq!(*calls.borrow(), vec![1.0, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn assignment_chains_bind_every_variable() {
+        let node = Parser::new("x = y = 5").unwrap().parse().unwrap();
+        assert_eq!(node, Node::Assign("x".into(), Box::new(
+            Node::Assign("y".into(), Box::new(Node::Number(5.0))))));
+        let mut ctx = Context::new();
+        assert_eq!(eval_with(&node, &mut ctx), Ok(5.0));
+        assert_eq!((ctx.get_var("x"), ctx.get_var("y")), (Some(5.0), Some(5.0)));
+        assert_eq!(eval_str("x = 1 == 1", &mut ctx), Ok(1.0));
+    }
+
+    #[test]
+    fn power_words() {
+        let mut ctx = Context::new();
+        assert_eq!(eval_str("3 squared == 9", &mut ctx), Ok(1.0));
+        assert_eq!(eval_str("2 cubed == 8", &mut ctx), Ok(1.0));
+        assert_eq!(eval_str("2 * 3 squared", &mut ctx), Ok(18.0));
+        assert!(Parser::new("squared = 2").unwrap().parse().is_err());
+        assert!(Parser::new("squared + 1").unwrap().parse().is_err());
+    }
+
+    #[test]
+    fn unknown_identifiers_fail_without_auto_vars() {
+        let node = Parser::new("x + 1").unwrap().parse().unwrap();
+        let mut ctx = Context::new();
+        assert_eq!(partial_eval(&node, &mut ctx), Err(EvalError::UnboundVariable("x".into())));
+        assert_eq!(eval_with(&node, &mut ctx), Err(EvalError::UnboundVariable("x".into())));
+    }
+
+    #[test]
+    fn auto_vars_keep_unknown_identifiers_symbolic() {
+        let partial = |expr: &str, ctx: &mut Context| {
+            partial_eval(&Parser::new(expr).unwrap().parse().unwrap(), ctx).unwrap().to_string()
+        };
+        let mut ctx = Context::new().auto_vars(true).with_var("y", 4.0);
+        assert_eq!(partial("x + 1", &mut ctx), "x + 1");
+        assert_eq!(partial("x + 2*3 + y", &mut ctx), "x + 6 + 4");
+        assert_eq!(partial("sqrt(y) * y", &mut ctx), "8");
+        assert_eq!(partial("z = x * 2", &mut ctx), "z = x*2");
+        assert_eq!(ctx.get_var("z"), None);
+        assert_eq!(eval_str("x + 1", &mut ctx), Err(EvalError::UnboundVariable("x".into())));
+    }
+
+    #[test]
+    fn mixed_numbers_add_the_fraction() {
+        let mut ctx = Context::new();
+        assert_eq!(eval_str("1 1/2 == 1.5", &mut ctx), Ok(1.0));
+        assert_eq!(eval_str("2 3/4 == 2.75", &mut ctx), Ok(1.0));
+        assert_eq!(eval_str("2 3/4", &mut ctx), Ok(2.75));
+        assert_eq!(eval_str("1/2", &mut ctx), Ok(0.5));
+        assert_eq!(eval_str("1 1/2 * 2", &mut ctx), Ok(3.0));
+    }
+
+    #[test]
+    fn rejects_malformed_mixed_numbers() {
+        assert!(Parser::new("1.5 1/2").unwrap().parse().is_err());
+        assert!(Parser::new("1 2").unwrap().parse().is_err());
+        assert!(Parser::new("1 1/x").unwrap().parse().is_err());
+        assert!(Parser::new("1 1/2.5").unwrap().parse().is_err());
+    }
+
+    #[test]
+    fn percent_change_from_x_to_y() {
+        let eval = |expr: &str, ctx: &mut Context| {
+            let node = Parser::from_tokenizer(Tokenizer::new(expr).percent_keywords(true))?.parse()?;
+            Ok::<f64, Box<dyn error::Error>>(eval_with(&node, ctx)?)
+        };
+        let mut ctx = Context::new();
+        assert_eq!(eval("from 50 to 75 == 50", &mut ctx).unwrap(), 1.0);
+        assert_eq!(eval("from 100 to 50 == -50", &mut ctx).unwrap(), 1.0);
+        assert_eq!(eval("from 2*5 to 3*5", &mut ctx).unwrap(), 50.0);
+        assert_eq!(eval("from 0 to 5", &mut ctx).unwrap_err().to_string(),
+            "Invalid argument: percent change from 0 is undefined");
+        assert!(eval("from 50", &mut ctx).is_err());
+        assert!(eval("50 to 75", &mut ctx).is_err());
+
+        // The construct does not go through a function the context could replace
+        let mut ctx = ctx.with_func("percent_change", Arity::Exact(2), |_| Ok(0.0));
+        assert_eq!(eval("from 50 to 75", &mut ctx).unwrap(), 50.0);
+    }
+
+    #[test]
+    fn from_and_to_are_names_by_default() {
+        let mut ctx = Context::new().with_var("from", 50.0).with_var("to", 75.0);
+        assert_eq!(eval_str("to - from", &mut ctx), Ok(25.0));
+    }
+}
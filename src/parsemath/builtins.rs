@@ -0,0 +1,278 @@
+//! This module contains the constants and functions registered in every default Context.
+use super::ast::EvalError;
+use super::context::{Arity, Function};
+use super::value::Value;
+
+/// Largest argument of `factorial` by default; 171! overflows f64
+pub const DEFAULT_FACTORIAL_LIMIT: u32 = 170;
+
+pub fn constants() -> Vec<(&'static str, f64)> {
+    vec![
+        ("pi", std::f64::consts::PI),
+        ("e", std::f64::consts::E),
+        ("tau", std::f64::consts::TAU),
+    ]
+}
+
+pub fn functions() -> Vec<(&'static str, Function)> {
+    vec![
+        ("sqrt", domain_checked("sqrt", f64::sqrt, |x| x >= 0.0, "must not be negative")),
+        ("abs", numeric(Value::abs)),
+        ("sign", numeric(Value::sign)),
+        ("sin", unary(f64::sin)),
+        ("cos", unary(f64::cos)),
+        ("tan", unary(f64::tan)),
+        ("asin", domain_checked("asin", f64::asin, |x| (-1.0..=1.0).contains(&x), "must be between -1 and 1")),
+        ("acos", domain_checked("acos", f64::acos, |x| (-1.0..=1.0).contains(&x), "must be between -1 and 1")),
+        ("atan", unary(f64::atan)),
+        ("exp", unary(f64::exp)),
+        ("ln", domain_checked("ln", f64::ln, |x| x > 0.0, "must be positive")),
+        ("log", domain_checked("log", f64::log10, |x| x > 0.0, "must be positive")),
+        ("floor", numeric(Value::floor)),
+        ("ceil", numeric(Value::ceil)),
+        ("round", unary(f64::round)),
+        // Marks a float for mixed integer evaluation, where float(2) shows 2.0; the identity otherwise
+        ("float", unary(|x| x)),
+        // Rounds halves to the nearest even integer to avoid bias, e.g. iround(2.5) == 2
+        ("iround", unary(f64::round_ties_even)),
+        ("min", Function::new(Arity::AtLeast(1), |args| {
+            Ok(args.iter().copied().fold(f64::INFINITY, f64::min))
+        })),
+        ("max", Function::new(Arity::AtLeast(1), |args| {
+            Ok(args.iter().copied().fold(f64::NEG_INFINITY, f64::max))
+        })),
+        ("factorial", factorial(DEFAULT_FACTORIAL_LIMIT)),
+        ("gamma", gamma(DEFAULT_FACTORIAL_LIMIT)),
+        ("wmean", Function::new(Arity::AtLeast(2), weighted_mean)),
+        // Greatest common divisor and least common multiple of all arguments, e.g. gcd(12, 18, 24) == 6
+        ("gcd", integer_fold("gcd", gcd)),
+        ("lcm", integer_fold("lcm", |a, b| if a == 0.0 || b == 0.0 { 0.0 } else { a / gcd(a, b) * b })),
+        ("clamp01", unary(|x| x.clamp(0.0, 1.0))),
+        // Linear interpolation a + (b - a) * t
+        ("lerp", Function::new(Arity::Exact(3), |args| {
+            Ok(args[0] + (args[1] - args[0]) * args[2])
+        })),
+    ]
+}
+
+/// Factorial of non-negative integers up to `limit`
+pub fn factorial(limit: u32) -> Function {
+    Function::new(Arity::Exact(1), move |args| {
+        let n = args[0];
+        if n < 0.0 || n.fract() != 0.0 {
+            return Err(EvalError::DomainError {
+                function: "factorial".into(),
+                value: n,
+                requirement: "must be a non-negative integer".into(),
+            });
+        }
+        if n > limit as f64 {
+            return Err(EvalError::OutOfRange { function: "factorial".into(), value: n, limit: limit as f64 });
+        }
+        Ok((2..=n as u32).map(f64::from).product())
+    })
+}
+
+/// Gamma function, where `gamma(n + 1) == factorial(n)`; arguments are capped at `limit + 1`
+pub fn gamma(limit: u32) -> Function {
+    Function::new(Arity::Exact(1), move |args| {
+        let x = args[0];
+        if x <= 0.0 && x.fract() == 0.0 {
+            return Err(EvalError::DomainError {
+                function: "gamma".into(),
+                value: x,
+                requirement: "must not be zero or a negative integer".into(),
+            });
+        }
+        let max = limit as f64 + 1.0;
+        if x > max {
+            return Err(EvalError::OutOfRange { function: "gamma".into(), value: x, limit: max });
+        }
+        if x.fract() == 0.0 {
+            return Ok((2..x as u32).map(f64::from).product());
+        }
+        Ok(lanczos_gamma(x))
+    })
+}
+
+/// Lanczos approximation with g = 7, accurate to about 15 digits
+fn lanczos_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection formula
+        return std::f64::consts::PI / ((std::f64::consts::PI * x).sin() * lanczos_gamma(1.0 - x));
+    }
+    let x = x - 1.0;
+    let sum = COEFFICIENTS[1..].iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |sum, (i, c)| sum + c / (x + i as f64 + 1.0));
+    let t = x + G + 0.5;
+    // t^(x + 0.5) alone overflows near the limit, so it is split around e^-t
+    let half_power = t.powf((x + 0.5) / 2.0);
+    (2.0 * std::f64::consts::PI).sqrt() * half_power * (half_power * (-t).exp()) * sum
+}
+
+/// Weighted mean of interleaved value, weight pairs: sum(v*w) / sum(w)
+fn weighted_mean(args: &[f64]) -> Result<f64, EvalError> {
+    if !args.len().is_multiple_of(2) {
+        return Err(EvalError::InvalidArgument(
+            "wmean expects value, weight pairs".into()));
+    }
+    let total_weight: f64 = args.chunks(2).map(|pair| pair[1]).sum();
+    if total_weight == 0.0 {
+        return Err(EvalError::InvalidArgument(
+            "wmean weights must not sum to zero".into()));
+    }
+    let weighted_sum: f64 = args.chunks(2).map(|pair| pair[0] * pair[1]).sum();
+    Ok(weighted_sum / total_weight)
+}
+
+/// Euclid's algorithm on non-negative integral floats, exact up to 2^53
+fn gcd(mut a: f64, mut b: f64) -> f64 {
+    while b != 0.0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// Reduces at least two integer arguments, taken without sign, with `f`
+fn integer_fold(name: &'static str, f: fn(f64, f64) -> f64) -> Function {
+    Function::new(Arity::AtLeast(2), move |args| {
+        if let Some(&value) = args.iter().find(|x| x.fract() != 0.0 || !x.is_finite()) {
+            return Err(EvalError::DomainError {
+                function: name.to_string(),
+                value,
+                requirement: "must be an integer".to_string(),
+            });
+        }
+        Ok(args[1..].iter().fold(args[0].abs(), |acc, x| f(acc, x.abs())))
+    })
+}
+
+/// Wraps a single argument float function
+fn unary(f: fn(f64) -> f64) -> Function {
+    Function::new(Arity::Exact(1), move |args| Ok(f(args[0])))
+}
+
+/// Wraps a numeric method of `Value`, shared with the integer preserving evaluation
+fn numeric(f: fn(&Value) -> Result<Value, EvalError>) -> Function {
+    Function::new(Arity::Exact(1), move |args| Ok(f(&Value::Float(args[0]))?.to_f64()))
+}
+
+/// Wraps a single argument float function that fails with a domain error outside of `valid`.
+/// NaN arguments are passed through.
+fn domain_checked(name: &'static str, f: fn(f64) -> f64, valid: fn(f64) -> bool,
+                  requirement: &'static str) -> Function {
+    Function::new(Arity::Exact(1), move |args| {
+        if !args[0].is_nan() && !valid(args[0]) {
+            return Err(EvalError::DomainError {
+                function: name.to_string(),
+                value: args[0],
+                requirement: requirement.to_string(),
+            });
+        }
+        Ok(f(args[0]))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::ast;
+    use super::super::context::Context;
+    use super::super::parser::Parser;
+
+    fn call(name: &str, args: &[f64]) -> Result<f64, EvalError> {
+        let ctx = Context::new();
+        ctx.get_func(name).expect("built-in function").call(name, args)
+    }
+
+    #[test]
+    fn iround_rounds_halves_to_even() {
+        assert_eq!(call("iround", &[2.5]), Ok(2.0));
+        assert_eq!(call("iround", &[3.5]), Ok(4.0));
+        assert_eq!(call("iround", &[2.6]), Ok(3.0));
+        assert_eq!(call("iround", &[-2.5]), Ok(-2.0));
+        assert_eq!(call("iround", &[-3.5]), Ok(-4.0));
+        assert_eq!(call("round", &[2.5]), Ok(3.0));
+    }
+
+    #[test]
+    fn weighted_mean_of_pairs() {
+        assert_eq!(call("wmean", &[1.0, 1.0, 3.0, 1.0]), Ok(2.0));
+        assert_eq!(call("wmean", &[1.0, 3.0, 5.0, 1.0]), Ok(2.0));
+        assert_eq!(call("wmean", &[1.0, 1.0, 3.0]), Err(EvalError::InvalidArgument(
+            "wmean expects value, weight pairs".into())));
+        assert_eq!(call("wmean", &[1.0, 1.0, 3.0, -1.0]), Err(EvalError::InvalidArgument(
+            "wmean weights must not sum to zero".into())));
+    }
+
+    #[test]
+    fn clamp01_and_lerp() {
+        assert_eq!(call("clamp01", &[1.5]), Ok(1.0));
+        assert_eq!(call("clamp01", &[-0.5]), Ok(0.0));
+        assert_eq!(call("clamp01", &[0.25]), Ok(0.25));
+        assert_eq!(call("lerp", &[0.0, 10.0, 0.5]), Ok(5.0));
+        assert_eq!(call("lerp", &[2.0, 4.0, 2.0]), Ok(6.0));
+        assert!(matches!(call("lerp", &[0.0, 10.0]), Err(EvalError::ArgumentCount { .. })));
+        assert!(matches!(call("clamp01", &[1.0, 2.0]), Err(EvalError::ArgumentCount { .. })));
+    }
+
+    #[test]
+    fn domain_errors_name_the_function() {
+        let error = |expr: &str| {
+            let node = Parser::new(expr).unwrap().parse().unwrap();
+            ast::eval_with(&node, &mut Context::new()).unwrap_err().to_string()
+        };
+        assert_eq!(error("log(sin(0) - 1)"), "log domain error: argument -1 must be positive");
+        assert_eq!(error("sqrt(-4)"), "sqrt domain error: argument -4 must not be negative");
+        assert_eq!(error("asin(2)"), "asin domain error: argument 2 must be between -1 and 1");
+        assert_eq!(call("sqrt", &[-4.0]), Err(EvalError::DomainError {
+            function: "sqrt".into(),
+            value: -4.0,
+            requirement: "must not be negative".into(),
+        }));
+    }
+
+    #[test]
+    fn factorial_is_capped() {
+        assert!(call("factorial", &[170.0]).unwrap().is_finite());
+        assert_eq!(call("factorial", &[5.0]), Ok(120.0));
+        assert_eq!(call("factorial", &[171.0]), Err(EvalError::OutOfRange {
+            function: "factorial".into(),
+            value: 171.0,
+            limit: 170.0,
+        }));
+        assert!(matches!(call("gamma", &[172.0]), Err(EvalError::OutOfRange { .. })));
+        assert_eq!(call("gamma", &[6.0]), Ok(120.0));
+    }
+
+    #[test]
+    fn factorial_limit_is_configurable() {
+        let ctx = Context::new().factorial_limit(10);
+        let factorial = ctx.get_func("factorial").unwrap();
+        assert_eq!(factorial.call("factorial", &[10.0]), Ok(3_628_800.0));
+        assert!(matches!(factorial.call("factorial", &[11.0]), Err(EvalError::OutOfRange { .. })));
+    }
+
+    #[test]
+    fn gcd_and_lcm_reduce_all_arguments() {
+        assert_eq!(call("gcd", &[12.0, 18.0, 24.0]), Ok(6.0));
+        assert_eq!(call("lcm", &[2.0, 3.0, 4.0]), Ok(12.0));
+        assert_eq!(call("gcd", &[-12.0, 18.0]), Ok(6.0));
+        assert_eq!(call("lcm", &[0.0, 5.0]), Ok(0.0));
+        assert!(matches!(call("gcd", &[12.0]), Err(EvalError::ArgumentCount { .. })));
+        assert!(matches!(call("lcm", &[2.0, 1.5]), Err(EvalError::DomainError { .. })));
+    }
+}
@@ -0,0 +1,245 @@
+//! This module caches results of whole evaluations, keyed by the expression text and the values
+//! of the variables it reads, so evaluating `x^2 + 1` again with the same `x` reuses the result.
+use std::collections::HashMap;
+
+use super::ast::{self, Node};
+use super::calc::CalcError;
+use super::context::{Context, Function, NanPolicy};
+use super::parser::Parser;
+
+/// Cache of evaluation results. A changed variable or evaluation setting of the context changes
+/// the key, including variables read by the bodies of called defined functions, so results
+/// never go stale from assignments. Functions are assumed to be pure and constants to stay
+/// the same.
+#[derive(Debug, Default)]
+pub struct EvalCache {
+    /// Parsed expressions with the names they read, or None when they assign variables
+    trees: HashMap<String, (Node, Option<Names>)>,
+    results: HashMap<Key, f64>,
+    hits: usize,
+    misses: usize,
+}
+
+impl EvalCache {
+    pub fn new() -> Self {
+        EvalCache::default()
+    }
+
+    /// Evaluates the expression, returning the cached result when it was already evaluated
+    /// with the same variable values. Expressions with assignments are always evaluated,
+    /// and errors are not cached.
+    pub fn eval(&mut self, expr: &str, ctx: &mut Context) -> Result<f64, CalcError> {
+        if !self.trees.contains_key(expr) {
+            let node = Parser::new(expr)?.parse()?;
+            let names = Names::of(&node);
+            let names = (!names.assigns).then_some(names);
+            self.trees.insert(expr.to_string(), (node, names));
+        }
+        let (node, names) = &self.trees[expr];
+        let names = match names {
+            Some(names) => names,
+            None => return Ok(ast::eval_with(node, ctx)?),
+        };
+
+        let key = Key {
+            expr: expr.to_string(),
+            values: variables_read(names, ctx).iter()
+                .map(|name| ctx.lookup(name).map(f64::to_bits))
+                .collect(),
+            settings: Settings::of(ctx),
+        };
+        if let Some(&value) = self.results.get(&key) {
+            self.hits += 1;
+            return Ok(value);
+        }
+        self.misses += 1;
+        let value = ast::eval_with(node, ctx)?;
+        self.results.insert(key, value);
+        Ok(value)
+    }
+
+    /// Number of evaluations answered from the cache
+    pub fn hits(&self) -> usize {
+        self.hits
+    }
+
+    /// Number of evaluations that had to be computed
+    pub fn misses(&self) -> usize {
+        self.misses
+    }
+
+    pub fn len(&self) -> usize {
+        self.results.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.results.is_empty()
+    }
+
+    /// Forgets all results, e.g. after functions of the context were replaced
+    pub fn clear(&mut self) {
+        self.trees.clear();
+        self.results.clear();
+    }
+}
+
+/// Names of the variables and functions an expression reads, and whether it assigns variables
+#[derive(Debug, Default)]
+struct Names {
+    variables: Vec<String>,
+    functions: Vec<String>,
+    assigns: bool,
+}
+
+impl Names {
+    fn of(node: &Node) -> Self {
+        fn collect(node: &Node, names: &mut Names) {
+            use self::Node::*;
+            match node {
+                Number(_) => {}
+                Variable(name) => names.variables.push(name.clone()),
+                Assign(_, expr) => {
+                    names.assigns = true;
+                    collect(expr, names);
+                }
+                Add(left, right) | Subtract(left, right) | Multiply(left, right)
+                | Divide(left, right) | Caret(left, right) | Equal(left, right)
+                | NotEqual(left, right) | PercentChange(left, right) => {
+                    collect(left, names);
+                    collect(right, names);
+                }
+                Negative(expr) => collect(expr, names),
+                Call(name, args) => {
+                    names.functions.push(name.clone());
+                    args.iter().for_each(|arg| collect(arg, names));
+                }
+            }
+        }
+
+        let mut names = Names::default();
+        collect(node, &mut names);
+        names
+    }
+}
+
+/// Sorted names of the variables read by the expression or by the bodies of the defined
+/// functions it calls, directly or through other functions. Parameters are local to their body.
+fn variables_read(names: &Names, ctx: &Context) -> Vec<String> {
+    let mut variables = names.variables.clone();
+    let mut pending = names.functions.clone();
+    let mut visited = Vec::new();
+    while let Some(name) = pending.pop() {
+        if visited.contains(&name) {
+            continue;
+        }
+        if let Some((params, body)) = ctx.get_func(&name).and_then(Function::defined_body) {
+            let body = Names::of(body);
+            variables.extend(body.variables.into_iter().filter(|variable| !params.contains(variable)));
+            pending.extend(body.functions);
+        }
+        visited.push(name);
+    }
+    variables.sort();
+    variables.dedup();
+    variables
+}
+
+/// Expression evaluated with the values of the names it reads, as bits, None for unbound names
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Key {
+    expr: String,
+    values: Vec<Option<u64>>,
+    settings: Settings,
+}
+
+/// Settings of the context that change results, floats as bits
+#[derive(Debug, PartialEq, Eq, Hash)]
+struct Settings {
+    input_scale: u64,
+    /// The NaN policy as its variant and default value
+    nan_policy: (u8, u64),
+    checked_integers: bool,
+    epsilon: u64,
+}
+
+impl Settings {
+    fn of(ctx: &Context) -> Self {
+        Settings {
+            input_scale: ctx.input_scale_factor().to_bits(),
+            nan_policy: match ctx.get_nan_policy() {
+                NanPolicy::Propagate => (0, 0),
+                NanPolicy::Error => (1, 0),
+                NanPolicy::Default(value) => (2, value.to_bits()),
+            },
+            checked_integers: ctx.checked_integers_enabled(),
+            epsilon: ctx.get_comparison_epsilon().to_bits(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_inputs_hit_the_cache() {
+        let mut cache = EvalCache::new();
+        let mut ctx = Context::new().with_var("x", 3.0);
+        assert_eq!(cache.eval("x^2 + 1", &mut ctx).unwrap(), 10.0);
+        assert_eq!(cache.eval("x^2 + 1", &mut ctx).unwrap(), 10.0);
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+    }
+
+    #[test]
+    fn changed_variables_miss_the_cache() {
+        let mut cache = EvalCache::new();
+        let mut ctx = Context::new().with_var("x", 3.0);
+        assert_eq!(cache.eval("x^2 + 1", &mut ctx).unwrap(), 10.0);
+        ctx.set_var("x", 4.0).unwrap();
+        assert_eq!(cache.eval("x^2 + 1", &mut ctx).unwrap(), 17.0);
+        assert_eq!((cache.hits(), cache.misses()), (0, 2));
+        ctx.set_var("x", 3.0).unwrap();
+        assert_eq!(cache.eval("x^2 + 1", &mut ctx).unwrap(), 10.0);
+        assert_eq!((cache.hits(), cache.len()), (1, 2));
+    }
+
+    #[test]
+    fn assignments_and_errors_are_not_cached() {
+        let mut cache = EvalCache::new();
+        let mut ctx = Context::new();
+        assert_eq!(cache.eval("y = 2", &mut ctx).unwrap(), 2.0);
+        assert_eq!(cache.eval("y = 2", &mut ctx).unwrap(), 2.0);
+        assert!(cache.eval("z + 1", &mut ctx).is_err());
+        assert!(cache.eval("z + 1", &mut ctx).is_err());
+        assert_eq!((cache.hits(), cache.misses()), (0, 2));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn changed_settings_miss_the_cache() {
+        let mut cache = EvalCache::new();
+        assert_eq!(cache.eval("50 + 50", &mut Context::new()).unwrap(), 100.0);
+        assert_eq!(cache.eval("50 + 50", &mut Context::new().input_scale(0.01)).unwrap(), 1.0);
+        assert!(cache.eval("asin(2)", &mut Context::new()).is_err());
+        let mut lenient = Context::new().nan_policy(NanPolicy::Default(0.0));
+        assert_eq!(cache.eval("asin(2)", &mut lenient).unwrap(), 0.0);
+        assert_eq!(cache.eval("50 + 50", &mut Context::new().checked_integers(true)).unwrap(), 100.0);
+        assert_eq!(cache.eval("0.1 + 0.2 == 0.3", &mut Context::new()).unwrap(), 0.0);
+        assert_eq!(cache.eval("0.1 + 0.2 == 0.3", &mut Context::new().comparison_epsilon(1e-9)).unwrap(), 1.0);
+        assert_eq!((cache.hits(), cache.misses()), (0, 7));
+    }
+
+    #[test]
+    fn variables_read_by_defined_functions_are_part_of_the_key() {
+        let mut cache = EvalCache::new();
+        let mut ctx = Context::new().with_var("rate", 2.0);
+        ctx.set_func("scaled", Function::defined(vec!["x".into()], Parser::new("x * rate").unwrap().parse().unwrap()));
+        ctx.set_func("twice", Function::defined(vec!["x".into()], Parser::new("scaled(x) * 2").unwrap().parse().unwrap()));
+        assert_eq!(cache.eval("twice(3)", &mut ctx).unwrap(), 12.0);
+        ctx.set_var("rate", 5.0).unwrap();
+        assert_eq!(cache.eval("twice(3)", &mut ctx).unwrap(), 30.0);
+        ctx.set_var("x", 1.0).unwrap();
+        assert_eq!(cache.eval("twice(3)", &mut ctx).unwrap(), 30.0);
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+    }
+}
@@ -0,0 +1,217 @@
+//! This module combines parsing and evaluation into convenient entry points.
+use std::error;
+use std::fmt;
+
+use super::ast::{self, EvalError};
+use super::context::{Context, NanPolicy};
+use super::format::{format_with, FormatOpts};
+use super::parser::{self, ParseErr};
+
+/// Error of either parsing or evaluating an expression
+#[derive(Debug)]
+pub enum CalcError {
+    Parse(ParseErr),
+    Eval(EvalError),
+}
+
+impl fmt::Display for CalcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            self::CalcError::Parse(e) => write!(f, "{}", e),
+            self::CalcError::Eval(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for CalcError {}
+
+impl From<ParseErr> for CalcError {
+    fn from(error: ParseErr) -> Self {
+        CalcError::Parse(error)
+    }
+}
+
+impl From<EvalError> for CalcError {
+    fn from(error: EvalError) -> Self {
+        CalcError::Eval(error)
+    }
+}
+
+/// Parses and evaluates a `;` separated program, returning the value of the last expression.
+/// The program may define functions, as in `f(x) = x^2 + 1; f(3)`.
+pub fn eval_str(expr: &str, ctx: &mut Context) -> Result<Option<f64>, CalcError> {
+    let program = parser::parse_statements(expr)?;
+    let values = ast::eval_statements(&program, ctx)?;
+    Ok(values.last().copied())
+}
+
+/// Parses and evaluates a program in a new default context and formats the value of the
+/// last statement. A program without statements gives an empty string.
+///
+/// ```
+/// use rcalc::parsemath::calc::eval_to_string;
+/// use rcalc::parsemath::format::FormatOpts;
+///
+/// assert_eq!(eval_to_string("1/3", &FormatOpts::fixed(2)).unwrap(), "0.33");
+/// ```
+pub fn eval_to_string(expr: &str, opts: &FormatOpts) -> Result<String, CalcError> {
+    let value = eval_str(expr, &mut Context::new())?;
+    Ok(value.map_or_else(String::new, |value| format_with(value, opts)))
+}
+
+/// Parses and evaluates a program in a new default context, treating non-finite results as no value:
+/// `1/0` and `sqrt(-1)` give `Ok(None)` while `2+2` gives `Ok(Some(4.0))`.
+/// Parse errors and other evaluation errors, such as unbound variables, are still errors.
+pub fn eval_finite(expr: &str) -> Result<Option<f64>, CalcError> {
+    let mut ctx = Context::new().nan_policy(NanPolicy::Propagate);
+    let value = eval_str(expr, &mut ctx)?;
+    Ok(value.filter(|value| value.is_finite()))
+}
+
+/// Evaluates lines one by one, sharing the context between them. See `eval_lines`.
+pub struct EvalLines<'c, I> {
+    lines: I,
+    ctx: &'c mut Context,
+    line_no: usize,
+}
+
+/// Iterates over the results of evaluating each line, paired with its 1-based line number.
+/// Everything after `#` is a comment, and lines without statements are skipped.
+/// A failing line does not stop the iteration.
+pub fn eval_lines<I, S>(lines: I, ctx: &mut Context) -> EvalLines<'_, I::IntoIter>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+{
+    EvalLines { lines: lines.into_iter(), ctx, line_no: 0 }
+}
+
+impl<'c, I, S> Iterator for EvalLines<'c, I>
+where
+    I: Iterator<Item = S>,
+    S: AsRef<str>,
+{
+    type Item = (usize, Result<f64, CalcError>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in self.lines.by_ref() {
+            self.line_no += 1;
+            let line = line.as_ref();
+            let code = match line.find('#') {
+                Some(comment) => &line[..comment],
+                None => line,
+            };
+
+            match eval_str(code, self.ctx) {
+                Ok(Some(value)) => return Some((self.line_no, Ok(value))),
+                Ok(None) => continue,
+                Err(error) => return Some((self.line_no, Err(error))),
+            }
+        }
+        None
+    }
+}
+
+/// Values at which `table` evaluates an expression: `from`, `from + step`, ... up to `to`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TableRange {
+    pub from: f64,
+    pub to: f64,
+    pub step: f64,
+}
+
+/// Largest number of rows `table` computes
+pub const MAX_TABLE_ROWS: usize = 10_000;
+
+/// Value of the variable and the result of the expression for it
+pub type TableRow = (f64, Result<f64, EvalError>);
+
+/// Evaluates the expression for each value of the range bound to `var`, e.g. `x^2` with x
+/// from 0 to 5. Each row holds the value of the variable and the result, so a failing row,
+/// like `ln(x)` at 0, does not stop the table. The variable keeps the last value afterwards.
+/// The step is negative for a range going down, and at most `MAX_TABLE_ROWS` rows are computed.
+pub fn table(
+    expr: &str,
+    var: &str,
+    range: TableRange,
+    ctx: &mut Context,
+) -> Result<Vec<TableRow>, CalcError> {
+    if !range.from.is_finite() || !range.to.is_finite() {
+        return Err(EvalError::InvalidArgument("table range must be finite".into()).into());
+    }
+    if range.step == 0.0 || !range.step.is_finite() {
+        return Err(EvalError::InvalidArgument(format!(
+            "table step must be a non-zero number, got {}", range.step)).into());
+    }
+    // Values are computed from the row index so steps like 0.1 do not accumulate errors,
+    // and the end is reached despite rounding
+    let rows = ((range.to - range.from) / range.step + 1e-9).floor();
+    if rows < 0.0 {
+        return Err(EvalError::InvalidArgument(format!(
+            "table step {} does not lead from {} to {}", range.step, range.from, range.to)).into());
+    }
+    if rows >= MAX_TABLE_ROWS as f64 {
+        return Err(EvalError::InvalidArgument(format!(
+            "table would have {} rows, at most {} are allowed", rows + 1.0, MAX_TABLE_ROWS)).into());
+    }
+    let node = parser::Parser::new(expr)?.parse()?;
+
+    let mut table = Vec::new();
+    for row in 0..=rows as usize {
+        let value = range.from + row as f64 * range.step;
+        let result = ctx.set_var(var, value).and_then(|()| ast::eval_with(&node, ctx));
+        table.push((value, result));
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_results_are_no_value() {
+        assert_eq!(eval_finite("1/0").unwrap(), None);
+        assert_eq!(eval_finite("sqrt(-1)").unwrap(), None);
+        assert_eq!(eval_finite("asin(2) + 1").unwrap(), None);
+        assert_eq!(eval_finite("2+2").unwrap(), Some(4.0));
+    }
+
+    #[test]
+    fn finite_evaluation_still_fails_on_errors() {
+        assert!(matches!(eval_finite("2 +"), Err(CalcError::Parse(_))));
+        assert!(matches!(eval_finite("x + 1"), Err(CalcError::Eval(EvalError::UnboundVariable(_)))));
+    }
+
+    #[test]
+    fn table_evaluates_each_value_of_the_range() {
+        let range = TableRange { from: 0.0, to: 0.3, step: 0.1 };
+        let rows = table("10*t", "t", range, &mut Context::new()).unwrap();
+        let values: Vec<f64> = rows.iter().map(|(t, _)| *t).collect();
+        assert_eq!(values, vec![0.0, 0.1, 0.2, 0.30000000000000004]);
+        assert_eq!(rows[1].1, Ok(1.0));
+    }
+
+    #[test]
+    fn failing_rows_do_not_stop_the_table() {
+        let range = TableRange { from: 0.0, to: 1.0, step: 1.0 };
+        let rows = table("ln(x)", "x", range, &mut Context::new()).unwrap();
+        assert!(matches!(rows[0].1, Err(EvalError::DomainError { .. })));
+        assert_eq!(rows[1], (1.0, Ok(0.0)));
+
+    }
+
+    #[test]
+    fn table_ranges_are_checked() {
+        let rows = table("x", "x", TableRange { from: 2.0, to: 0.0, step: -1.0 }, &mut Context::new()).unwrap();
+        assert_eq!(rows.iter().map(|(x, _)| *x).collect::<Vec<f64>>(), vec![2.0, 1.0, 0.0]);
+
+        let error = |from, to, step| {
+            table("x", "x", TableRange { from, to, step }, &mut Context::new()).unwrap_err().to_string()
+        };
+        assert_eq!(error(0.0, 1.0, 0.0), "Invalid argument: table step must be a non-zero number, got 0");
+        assert_eq!(error(0.0, 5.0, -1.0), "Invalid argument: table step -1 does not lead from 0 to 5");
+        assert_eq!(error(0.0, 1e12, 1.0), "Invalid argument: table would have 1000000000001 rows, at most 10000 are allowed");
+        assert_eq!(table("x", "x", TableRange { from: 1.0, to: 10_000.0, step: 1.0 }, &mut Context::new()).unwrap().len(), 10_000);
+    }
+}
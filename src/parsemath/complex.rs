@@ -0,0 +1,296 @@
+//! This module evaluates AST over complex numbers, available with the `complex` feature.
+//! The name `i` is the imaginary unit unless a variable of that name is bound,
+//! so `sqrt(-1)` is `i` and `(1+2i)*(3+4i)` is `-5 + 10i`.
+use std::fmt;
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+use super::ast::{self, EvalError, Node};
+use super::context::Context;
+use super::format::format_result;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+pub const I: Complex = Complex { re: 0.0, im: 1.0 };
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Self {
+        Complex { re, im }
+    }
+
+    pub fn real(re: f64) -> Self {
+        Complex { re, im: 0.0 }
+    }
+
+    pub fn is_real(&self) -> bool {
+        self.im == 0.0
+    }
+
+    pub fn conj(&self) -> Self {
+        Complex::new(self.re, -self.im)
+    }
+
+    pub fn abs(&self) -> f64 {
+        self.re.hypot(self.im)
+    }
+
+    pub fn arg(&self) -> f64 {
+        self.im.atan2(self.re)
+    }
+
+    pub fn exp(&self) -> Self {
+        let scale = self.re.exp();
+        Complex::new(scale * self.im.cos(), scale * self.im.sin())
+    }
+
+    /// Principal natural logarithm
+    pub fn ln(&self) -> Self {
+        Complex::new(self.abs().ln(), self.arg())
+    }
+
+    /// Principal square root. Negative reals give an exact imaginary result.
+    pub fn sqrt(&self) -> Self {
+        if self.is_real() {
+            return if self.re >= 0.0 {
+                Complex::real(self.re.sqrt())
+            } else {
+                Complex::new(0.0, (-self.re).sqrt())
+            };
+        }
+        let modulus = self.abs();
+        let re = ((modulus + self.re) / 2.0).sqrt();
+        let im = ((modulus - self.re) / 2.0).sqrt().copysign(self.im);
+        Complex::new(re, im)
+    }
+
+    pub fn sin(&self) -> Self {
+        Complex::new(self.re.sin() * self.im.cosh(), self.re.cos() * self.im.sinh())
+    }
+
+    pub fn cos(&self) -> Self {
+        Complex::new(self.re.cos() * self.im.cosh(), -(self.re.sin() * self.im.sinh()))
+    }
+
+    /// Raises to a power. Integer exponents use repeated multiplication so `i^2` is exactly `-1`.
+    pub fn pow(&self, exponent: Complex) -> Result<Self, EvalError> {
+        if *self == Complex::real(0.0) {
+            return if exponent == Complex::real(0.0) {
+                Ok(Complex::real(1.0))
+            } else if exponent.re < 0.0 {
+                Err(EvalError::DivisionByZero)
+            } else {
+                Ok(Complex::real(0.0))
+            };
+        }
+        if exponent.is_real() && exponent.re.fract() == 0.0 && exponent.re.abs() <= 1024.0 {
+            let mut result = Complex::real(1.0);
+            let mut base = *self;
+            let mut n = exponent.re.abs() as u32;
+            while n > 0 {
+                if n & 1 == 1 {
+                    result = result * base;
+                }
+                base = base * base;
+                n >>= 1;
+            }
+            return Ok(if exponent.re < 0.0 { Complex::real(1.0) / result } else { result });
+        }
+        if self.is_real() && self.re > 0.0 && exponent.is_real() {
+            return Ok(Complex::real(self.re.powf(exponent.re)));
+        }
+        Ok((exponent * self.ln()).exp())
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl Div for Complex {
+    type Output = Complex;
+    fn div(self, other: Complex) -> Complex {
+        let denominator = other.re * other.re + other.im * other.im;
+        let numerator = self * other.conj();
+        Complex::new(numerator.re / denominator, numerator.im / denominator)
+    }
+}
+
+impl Neg for Complex {
+    type Output = Complex;
+    fn neg(self) -> Complex {
+        Complex::new(-self.re, -self.im)
+    }
+}
+
+/// Displays as `3 + 4i`, omitting a zero real or imaginary part. A part that is only
+/// rounding noise next to the other one, as in `exp(i*pi)`, is shown as zero.
+impl fmt::Display for Complex {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let noise = self.abs() * 1e-15;
+        let re = if self.re.abs() < noise { 0.0 } else { self.re };
+        let im = if self.im.abs() < noise { 0.0 } else { self.im };
+        let imaginary = |im: f64| match format_result(im).as_str() {
+            "1" => "i".to_string(),
+            digits => format!("{}i", digits),
+        };
+        if im == 0.0 {
+            write!(f, "{}", format_result(re))
+        } else if re == 0.0 && im < 0.0 {
+            write!(f, "-{}", imaginary(-im))
+        } else if re == 0.0 {
+            write!(f, "{}", imaginary(im))
+        } else if im < 0.0 {
+            write!(f, "{} - {}", format_result(re), imaginary(-im))
+        } else {
+            write!(f, "{} + {}", format_result(re), imaginary(im))
+        }
+    }
+}
+
+/// Evaluates the AST over complex numbers. Functions without a complex implementation
+/// are called with real arguments and fail for arguments with an imaginary part.
+pub fn eval_complex(expr: &Node, ctx: &mut Context) -> Result<Complex, EvalError> {
+    use self::Node::*;
+    match expr {
+        Number(n) => Ok(Complex::real(*n * ctx.input_scale_factor())),
+        Variable(name) => match ctx.lookup(name) {
+            Some(value) => Ok(Complex::real(value)),
+            None if name == "i" => Ok(I),
+            None => Err(EvalError::UnboundVariable(name.clone())),
+        },
+        Add(expr1, expr2) => Ok(
+            eval_complex(expr1, ctx)? + eval_complex(expr2, ctx)?
+        ),
+        Subtract(expr1, expr2) => Ok(
+            eval_complex(expr1, ctx)? - eval_complex(expr2, ctx)?
+        ),
+        Multiply(expr1, expr2) => Ok(
+            eval_complex(expr1, ctx)? * eval_complex(expr2, ctx)?
+        ),
+        Divide(expr1, expr2) => Ok(
+            eval_complex(expr1, ctx)? / eval_complex(expr2, ctx)?
+        ),
+        Caret(expr1, expr2) => {
+            let base = eval_complex(expr1, ctx)?;
+            base.pow(ctx.unscaled(|ctx| eval_complex(expr2, ctx))?)
+        }
+        Negative(expr1) => Ok(-eval_complex(expr1, ctx)?),
+        Call(name, args) => {
+            let args = args.iter()
+                .map(|arg| eval_complex(arg, ctx))
+                .collect::<Result<Vec<Complex>, EvalError>>()?;
+            call_complex(name, &args, ctx)
+        }
+        Assign(name, expr1) => {
+            let value = eval_complex(expr1, ctx)?;
+            if !value.is_real() {
+                return Err(EvalError::InvalidArgument(format!(
+                    "can not store complex value {} in variable {}", value, name)));
+            }
+            ctx.set_var(name, value.re)?;
+            Ok(value)
+        }
+        Equal(expr1, expr2) | NotEqual(expr1, expr2) => {
+            let left = eval_complex(expr1, ctx)?;
+            let right = eval_complex(expr2, ctx)?;
+            // Within the epsilon of each other in the complex plane
+            let equal = left == right || ctx.approx_eq((left - right).abs(), 0.0);
+            Ok(Complex::real(if equal != matches!(expr, NotEqual(..)) { 1.0 } else { 0.0 }))
+        }
+        PercentChange(expr1, expr2) => {
+            let from = eval_complex(expr1, ctx)?;
+            let to = eval_complex(expr2, ctx)?;
+            // Changes from zero fail like real ones
+            if (from.is_real() && to.is_real()) || from == Complex::real(0.0) {
+                return Ok(Complex::real(ast::percent_change(from.re, to.re)?));
+            }
+            Ok((to - from) / from * Complex::real(100.0))
+        }
+    }
+}
+
+fn call_complex(name: &str, args: &[Complex], ctx: &mut Context) -> Result<Complex, EvalError> {
+    match (name, args) {
+        ("sqrt", [z]) => return Ok(z.sqrt()),
+        ("exp", [z]) => return Ok(z.exp()),
+        ("ln", [z]) => return Ok(z.ln()),
+        ("sin", [z]) => return Ok(z.sin()),
+        ("cos", [z]) => return Ok(z.cos()),
+        ("abs", [z]) => return Ok(Complex::real(z.abs())),
+        ("arg", [z]) => return Ok(Complex::real(z.arg())),
+        ("re", [z]) => return Ok(Complex::real(z.re)),
+        ("im", [z]) => return Ok(Complex::real(z.im)),
+        ("conj", [z]) => return Ok(z.conj()),
+        _ => {}
+    }
+
+    let func = ctx.get_func(name).cloned()
+        .ok_or_else(|| EvalError::UnknownFunction(name.to_string()))?;
+    let real_args = args.iter()
+        .map(|z| if z.is_real() {
+            Ok(z.re)
+        } else {
+            Err(EvalError::InvalidArgument(format!(
+                "{} does not accept complex argument {}", name, z)))
+        })
+        .collect::<Result<Vec<f64>, EvalError>>()?;
+    Ok(Complex::real(ctx.call_in_scope(name, &func, &real_args)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::Parser;
+
+    fn eval(expr: &str) -> Complex {
+        eval_complex(&Parser::new(expr).unwrap().parse().unwrap(), &mut Context::new()).unwrap()
+    }
+
+    #[test]
+    fn square_root_of_minus_one_is_i() {
+        assert_eq!(eval("sqrt(-1)"), I);
+        assert_eq!(eval("sqrt(-1)").to_string(), "i");
+    }
+
+    #[test]
+    fn i_squared_is_minus_one() {
+        assert_eq!(eval("i*i"), Complex::real(-1.0));
+        assert_eq!(eval("i*i == -1"), Complex::real(1.0));
+    }
+
+    #[test]
+    fn multiplies_complex_numbers() {
+        assert_eq!(eval("(1+2i)*(3+4i)"), Complex::new(-5.0, 10.0));
+        assert_eq!(eval("(1+2i)*(3+4i)").to_string(), "-5 + 10i");
+        assert_eq!(eval("3 - 4i").to_string(), "3 - 4i");
+    }
+
+    #[test]
+    fn real_functions_reject_complex_arguments() {
+        let node = Parser::new("floor(i)").unwrap().parse().unwrap();
+        assert!(matches!(eval_complex(&node, &mut Context::new()), Err(EvalError::InvalidArgument(_))));
+    }
+}
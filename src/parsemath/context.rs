@@ -0,0 +1,618 @@
+//! This module contains the state shared between evaluations: variables, constants and functions.
+use std::cell::{Cell, OnceCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use super::ast::{self, EvalError, Node, Warning};
+use super::builtins;
+
+/// Native implementation of a function, receiving already evaluated arguments
+pub type NativeFn = Rc<dyn Fn(&[f64]) -> Result<f64, EvalError>>;
+
+/// Number of arguments a function accepts
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    AtLeast(usize),
+}
+
+impl Arity {
+    pub fn accepts(&self, count: usize) -> bool {
+        match *self {
+            Arity::Exact(n) => count == n,
+            Arity::AtLeast(n) => count >= n,
+        }
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Arity::Exact(n) => write!(f, "{}", n),
+            Arity::AtLeast(n) => write!(f, "at least {}", n),
+        }
+    }
+}
+
+/// What happens when a function has no real result, either returning NaN like `sin(inf)`
+/// or failing with a domain error like `asin(2)`
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NanPolicy {
+    /// The call evaluates to NaN, which propagates through the rest of the expression
+    Propagate,
+    /// The call fails, with the domain error if there is one
+    #[default]
+    Error,
+    /// The call evaluates to the given value instead
+    Default(f64),
+}
+
+impl NanPolicy {
+    /// Applies the policy to the result of calling the function
+    pub fn apply(&self, function: &str, result: Result<f64, EvalError>) -> Result<f64, EvalError> {
+        match (result, self) {
+            (Ok(value), _) if !value.is_nan() => Ok(value),
+            (Ok(_) | Err(EvalError::DomainError { .. }), NanPolicy::Propagate) => Ok(f64::NAN),
+            (Ok(_) | Err(EvalError::DomainError { .. }), NanPolicy::Default(value)) => Ok(*value),
+            (Ok(_), NanPolicy::Error) => Err(EvalError::NotANumber(function.to_string())),
+            (Err(e), _) => Err(e),
+        }
+    }
+}
+
+/// Nesting of function calls allowed by default, see `Context::max_function_recursion`
+pub const DEFAULT_RECURSION_LIMIT: usize = 100;
+
+thread_local! {
+    /// Function calls in progress on this thread across all contexts, with the smallest
+    /// recursion limit of the contexts making them
+    static CALL_CHAIN: Cell<(usize, usize)> = const { Cell::new((0, usize::MAX)) };
+}
+
+/// Counts a function call in progress on the thread until dropped, also when the call fails
+struct CallGuard {
+    /// Limit of the chain before this call
+    outer_limit: usize,
+}
+
+impl CallGuard {
+    /// Enters a call of a context with `depth` calls in progress, failing beyond its limit
+    /// or the smallest limit of the calls in progress
+    fn enter(name: &str, depth: usize, limit: usize) -> Result<Self, EvalError> {
+        CALL_CHAIN.with(|chain| {
+            let (chain_depth, outer_limit) = chain.get();
+            if depth >= limit || chain_depth >= outer_limit.min(limit) {
+                return Err(EvalError::RecursionLimit(name.to_string()));
+            }
+            chain.set((chain_depth + 1, outer_limit.min(limit)));
+            Ok(CallGuard { outer_limit })
+        })
+    }
+}
+
+impl Drop for CallGuard {
+    fn drop(&mut self) {
+        CALL_CHAIN.with(|chain| chain.set((chain.get().0 - 1, self.outer_limit)));
+    }
+}
+
+/// How a function computes its result
+#[derive(Clone)]
+enum Body {
+    Native(NativeFn),
+    /// Expression evaluated in the calling context, with the arguments bound to the parameters
+    Expr { params: Rc<[String]>, expr: Rc<Node> },
+}
+
+/// Function that can be called from expressions
+#[derive(Clone)]
+pub struct Function {
+    arity: Arity,
+    body: Body,
+}
+
+impl Function {
+    pub fn new(arity: Arity, body: impl Fn(&[f64]) -> Result<f64, EvalError> + 'static) -> Self {
+        Function { arity, body: Body::Native(Rc::new(body)) }
+    }
+
+    /// Creates a function evaluating the expression with the arguments bound to the parameters.
+    /// The expression is evaluated at call time in a child scope of the calling context.
+    pub fn defined(params: Vec<String>, expr: Node) -> Self {
+        Function {
+            arity: Arity::Exact(params.len()),
+            body: Body::Expr { params: params.into(), expr: Rc::new(expr) },
+        }
+    }
+
+    /// Creates a function whose body also receives the function itself, so it can call itself
+    pub fn recursive(
+        arity: Arity,
+        body: impl Fn(&Function, &[f64]) -> Result<f64, EvalError> + 'static,
+    ) -> Self {
+        // The body only keeps a weak reference to itself, so the function is still dropped
+        let this = Rc::new(OnceCell::<Weak<dyn Fn(&[f64]) -> Result<f64, EvalError>>>::new());
+        let weak = this.clone();
+        let native: NativeFn = Rc::new(move |args| {
+            let itself = weak.get()
+                .and_then(Weak::upgrade)
+                .expect("a function is alive while it is called");
+            body(&Function { arity, body: Body::Native(itself) }, args)
+        });
+        let _ = this.set(Rc::downgrade(&native));
+        Function { arity, body: Body::Native(native) }
+    }
+
+    pub fn arity(&self) -> Arity {
+        self.arity
+    }
+
+    /// Parameters and expression of a function created with `defined`
+    pub(crate) fn defined_body(&self) -> Option<(&[String], &Node)> {
+        match &self.body {
+            Body::Native(_) => None,
+            Body::Expr { params, expr } => Some((params, expr)),
+        }
+    }
+
+    /// Checks the number of arguments and invokes the function. A defined body is evaluated
+    /// with only the built-in constants and functions; `Context::call_func` evaluates it
+    /// against a context instead.
+    pub fn call(&self, name: &str, args: &[f64]) -> Result<f64, EvalError> {
+        self.call_in(name, args, &mut Context::new())
+    }
+
+    /// Checks the number of arguments and invokes the function, evaluating a defined body
+    /// in a new innermost scope of the context
+    fn call_in(&self, name: &str, args: &[f64], ctx: &mut Context) -> Result<f64, EvalError> {
+        if !self.arity.accepts(args.len()) {
+            return Err(EvalError::ArgumentCount {
+                function: name.to_string(),
+                expected: self.arity,
+                got: args.len(),
+            });
+        }
+        match &self.body {
+            Body::Native(native) => native(args),
+            Body::Expr { params, expr } => {
+                let frame = params.iter().cloned().zip(args.iter().copied()).collect();
+                ctx.frames.push(frame);
+                let result = ast::eval_with(expr, ctx);
+                ctx.frames.pop();
+                result
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Function").field("arity", &self.arity).finish()
+    }
+}
+
+/// Evaluation state. `Context::new` comes with the built-in constants and functions registered.
+#[derive(Clone, Debug)]
+pub struct Context {
+    vars: HashMap<String, f64>,
+    /// Parameters and local variables of the defined functions being called, innermost last
+    frames: Vec<HashMap<String, f64>>,
+    consts: HashMap<String, f64>,
+    funcs: HashMap<String, Function>,
+    ans: Option<f64>,
+    precision_warnings: bool,
+    warnings: Vec<Warning>,
+    input_scale: f64,
+    nan_policy: NanPolicy,
+    auto_vars: bool,
+    recursion_limit: usize,
+    /// Function calls of this context in progress
+    depth: Cell<usize>,
+    checked_integers: bool,
+    epsilon: f64,
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        let mut ctx = Context::empty();
+        for (name, value) in builtins::constants() {
+            ctx.consts.insert(name.to_string(), value);
+        }
+        for (name, func) in builtins::functions() {
+            ctx.funcs.insert(name.to_string(), func);
+        }
+        ctx
+    }
+}
+
+// Builder methods
+impl Context {
+    pub fn new() -> Self {
+        Context::default()
+    }
+
+    /// Creates a context without any built-in constants or functions
+    pub fn empty() -> Self {
+        Context {
+            vars: HashMap::new(),
+            frames: Vec::new(),
+            consts: HashMap::new(),
+            funcs: HashMap::new(),
+            ans: None,
+            precision_warnings: false,
+            warnings: Vec::new(),
+            input_scale: 1.0,
+            nan_policy: NanPolicy::default(),
+            auto_vars: false,
+            recursion_limit: DEFAULT_RECURSION_LIMIT,
+            depth: Cell::new(0),
+            checked_integers: false,
+            epsilon: 0.0,
+        }
+    }
+
+    pub fn with_var(mut self, name: &str, value: f64) -> Self {
+        self.vars.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn with_const(mut self, name: &str, value: f64) -> Self {
+        self.consts.insert(name.to_string(), value);
+        self
+    }
+
+    pub fn with_func(
+        mut self,
+        name: &str,
+        arity: Arity,
+        body: impl Fn(&[f64]) -> Result<f64, EvalError> + 'static,
+    ) -> Self {
+        self.funcs.insert(name.to_string(), Function::new(arity, body));
+        self
+    }
+
+    /// Sets the largest argument accepted by `factorial`, and by `gamma` shifted by one.
+    /// Arguments above it fail instead of overflowing to infinity.
+    pub fn factorial_limit(mut self, limit: u32) -> Self {
+        self.funcs.insert("factorial".to_string(), builtins::factorial(limit));
+        self.funcs.insert("gamma".to_string(), builtins::gamma(limit));
+        self
+    }
+
+    /// Multiplies every number literal by the factor during evaluation, e.g. with `0.01`
+    /// numbers are read as percentages and `50` evaluates to `0.5`. Variables are not scaled,
+    /// and neither are exponents, which count factors: `50^2` and `50 squared` are `0.25`.
+    pub fn input_scale(mut self, factor: f64) -> Self {
+        self.input_scale = factor;
+        self
+    }
+
+    /// Treats unknown identifiers as free variables in `partial_eval` instead of failing.
+    /// Full evaluation still needs a value for every variable.
+    pub fn auto_vars(mut self, enabled: bool) -> Self {
+        self.auto_vars = enabled;
+        self
+    }
+
+    /// Sets what function calls without a real result evaluate to, failing by default
+    pub fn nan_policy(mut self, policy: NanPolicy) -> Self {
+        self.nan_policy = policy;
+        self
+    }
+
+    /// Sets how deeply function calls may nest, e.g. a registered function evaluating
+    /// an expression that calls it again. Deeper calls fail instead of overflowing the stack.
+    /// When contexts call each other's functions, the smallest limit among them applies.
+    pub fn max_function_recursion(mut self, limit: usize) -> Self {
+        self.recursion_limit = limit;
+        self
+    }
+
+    /// Makes integer arithmetic of `value::eval_value` that overflows i64, such as `2^63`,
+    /// fail with `EvalError::Overflow` instead of continuing with floats
+    pub fn checked_integers(mut self, enabled: bool) -> Self {
+        self.checked_integers = enabled;
+        self
+    }
+
+    /// Makes `==` true and `!=` false for numbers at most `epsilon` apart, so `0.1 + 0.2 == 0.3`
+    /// holds with `1e-9`. Comparisons are exact by default.
+    pub fn comparison_epsilon(mut self, epsilon: f64) -> Self {
+        self.epsilon = epsilon;
+        self
+    }
+
+    /// Enables warnings when an addition loses an operand to float precision
+    pub fn precision_warnings(mut self, enabled: bool) -> Self {
+        self.precision_warnings = enabled;
+        self
+    }
+}
+
+// Accessors used during evaluation
+impl Context {
+    /// Resolves an identifier: locals of the innermost function call first, then variables,
+    /// then constants, then `ans`
+    pub fn lookup(&self, name: &str) -> Option<f64> {
+        self.frames.last()
+            .and_then(|frame| frame.get(name))
+            .or_else(|| self.vars.get(name))
+            .or_else(|| self.consts.get(name))
+            .copied()
+            .or(if name == "ans" { self.ans } else { None })
+    }
+
+    /// Returns the value bound to a variable
+    pub fn get_var(&self, name: &str) -> Option<f64> {
+        self.vars.get(name).copied()
+    }
+
+    /// Binds a value to a variable, replacing any previous value. Constants can not be reassigned,
+    /// except for parameters shadowing them. Inside a defined function the variable is local.
+    pub fn set_var(&mut self, name: &str, value: f64) -> Result<(), EvalError> {
+        if let Some(local) = self.frames.last_mut().and_then(|frame| frame.get_mut(name)) {
+            *local = value;
+            return Ok(());
+        }
+        if self.consts.contains_key(name) {
+            return Err(EvalError::ConstantAssignment(name.to_string()));
+        }
+        match self.frames.last_mut() {
+            Some(frame) => frame.insert(name.to_string(), value),
+            None => self.vars.insert(name.to_string(), value),
+        };
+        Ok(())
+    }
+
+    /// Iterates over the user variables in no particular order
+    pub fn vars(&self) -> impl Iterator<Item = (&str, f64)> {
+        self.vars.iter().map(|(name, value)| (name.as_str(), *value))
+    }
+
+    /// Defines a constant, replacing any previous one of the same name
+    pub fn set_const(&mut self, name: &str, value: f64) {
+        self.consts.insert(name.to_string(), value);
+    }
+
+    pub fn get_const(&self, name: &str) -> Option<f64> {
+        self.consts.get(name).copied()
+    }
+
+    pub fn get_func(&self, name: &str) -> Option<&Function> {
+        self.funcs.get(name)
+    }
+
+    /// Registers a function, replacing any previous one of the same name
+    pub fn set_func(&mut self, name: &str, func: Function) {
+        self.funcs.insert(name.to_string(), func);
+    }
+
+    /// Calls the function and applies the NaN policy to its result. Nesting is limited by the
+    /// recursion limit of the context. Calls are also counted per thread, so functions evaluating
+    /// expressions in another context are limited too; there the smallest limit in the chain wins.
+    /// A defined body is evaluated in a copy of the context, so warnings it raises are dropped.
+    pub fn call_func(&self, name: &str, func: &Function, args: &[f64]) -> Result<f64, EvalError> {
+        if func.defined_body().is_some() {
+            return self.clone().call_in_scope(name, func, args);
+        }
+        let _guard = CallGuard::enter(name, self.depth.get(), self.recursion_limit)?;
+        self.nan_policy.apply(name, func.call(name, args))
+    }
+
+    /// Calls the function like `call_func`, evaluating a defined body in a new innermost scope
+    /// of this context, so evaluators keep the warnings it raises
+    pub(crate) fn call_in_scope(&mut self, name: &str, func: &Function, args: &[f64]) -> Result<f64, EvalError> {
+        let _guard = CallGuard::enter(name, self.depth.get(), self.recursion_limit)?;
+        self.depth.set(self.depth.get() + 1);
+        let result = func.call_in(name, args, self);
+        self.depth.set(self.depth.get() - 1);
+        self.nan_policy.apply(name, result)
+    }
+
+    /// Result of the last evaluated statement
+    pub fn ans(&self) -> Option<f64> {
+        self.ans
+    }
+
+    pub fn set_ans(&mut self, value: f64) {
+        self.ans = Some(value);
+    }
+
+    /// Factor applied to number literals, 1 unless set with `input_scale`
+    pub fn input_scale_factor(&self) -> f64 {
+        self.input_scale
+    }
+
+    /// Runs the evaluation with number literals unscaled, as for exponents
+    pub fn unscaled<T>(&mut self, eval: impl FnOnce(&mut Context) -> T) -> T {
+        let scale = std::mem::replace(&mut self.input_scale, 1.0);
+        let result = eval(self);
+        self.input_scale = scale;
+        result
+    }
+
+    pub fn auto_vars_enabled(&self) -> bool {
+        self.auto_vars
+    }
+
+    pub fn get_nan_policy(&self) -> NanPolicy {
+        self.nan_policy
+    }
+
+    pub fn get_comparison_epsilon(&self) -> f64 {
+        self.epsilon
+    }
+
+    /// Compares numbers for `==` and `!=`, within the comparison epsilon
+    pub fn approx_eq(&self, a: f64, b: f64) -> bool {
+        a == b || (a - b).abs() <= self.epsilon
+    }
+
+    pub fn checked_integers_enabled(&self) -> bool {
+        self.checked_integers
+    }
+
+    pub fn precision_warnings_enabled(&self) -> bool {
+        self.precision_warnings
+    }
+
+    pub fn warn(&mut self, warning: Warning) {
+        self.warnings.push(warning);
+    }
+
+    /// Returns the warnings collected so far and clears them
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+
+    /// Drops all user variables and `ans`, keeping constants and functions
+    pub fn clear_vars(&mut self) {
+        self.vars.clear();
+        self.ans = None;
+    }
+
+    /// Resets the context to the defaults of `Context::new`
+    pub fn clear_all(&mut self) {
+        *self = Context::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{ast, explain, memo, parser::Parser};
+
+    fn eval(expr: &str, ctx: &mut Context) -> Result<f64, EvalError> {
+        ast::eval_with(&Parser::new(expr).unwrap().parse().unwrap(), ctx)
+    }
+
+    /// Counts the call and evaluates `f(x + 1)` in a new context with the recursion limit,
+    /// where `f` calls this again, without end
+    fn endless(args: &[f64], limit: usize, calls: Rc<Cell<usize>>) -> Result<f64, EvalError> {
+        calls.set(calls.get() + 1);
+        let mut inner = Context::new().max_function_recursion(limit).with_var("x", args[0])
+            .with_func("f", Arity::Exact(1), move |args| endless(args, limit, calls.clone()));
+        eval("f(x + 1)", &mut inner)
+    }
+
+    #[test]
+    fn evaluates_against_variables_and_functions() {
+        let mut ctx = Context::new()
+            .with_var("x", 3.0)
+            .with_func("double", Arity::Exact(1), |args| Ok(args[0] * 2.0));
+        assert_eq!(eval("double(x) + 1", &mut ctx), Ok(7.0));
+        assert_eq!(eval("pi", &mut ctx), Ok(std::f64::consts::PI));
+        assert_eq!(eval("sqrt(16)", &mut ctx), Ok(4.0));
+    }
+
+    #[test]
+    fn defined_functions_can_be_called_directly() {
+        let shifted = Function::defined(vec!["x".into()], Parser::new("x * 2 + y").unwrap().parse().unwrap());
+        let ctx = Context::new().with_var("y", 1.0);
+        assert_eq!(ctx.call_func("shifted", &shifted, &[3.0]), Ok(7.0));
+        assert_eq!(shifted.call("shifted", &[3.0]), Err(EvalError::UnboundVariable("y".into())));
+        assert!(matches!(shifted.call("shifted", &[]), Err(EvalError::ArgumentCount { .. })));
+    }
+
+    #[test]
+    fn empty_context_has_no_builtins() {
+        let mut ctx = Context::empty();
+        assert_eq!(eval("pi", &mut ctx), Err(EvalError::UnboundVariable("pi".into())));
+        assert_eq!(eval("sqrt(4)", &mut ctx), Err(EvalError::UnknownFunction("sqrt".into())));
+    }
+
+    #[test]
+    fn clear_vars_keeps_constants_and_functions() {
+        let mut ctx = Context::new();
+        assert_eq!(eval("x = 5", &mut ctx), Ok(5.0));
+        ctx.set_ans(5.0);
+        ctx.clear_vars();
+        assert_eq!(eval("x", &mut ctx), Err(EvalError::UnboundVariable("x".into())));
+        assert_eq!(ctx.ans(), None);
+        assert_eq!(eval("pi", &mut ctx), Ok(std::f64::consts::PI));
+        assert_eq!(eval("sqrt(9)", &mut ctx), Ok(3.0));
+    }
+
+    #[test]
+    fn clear_all_restores_defaults() {
+        let mut ctx = Context::new().with_const("g", 9.81).with_var("x", 1.0)
+            .with_func("sqrt", Arity::Exact(1), |_| Ok(0.0));
+        ctx.clear_all();
+        assert_eq!(ctx.get_const("g"), None);
+        assert_eq!(ctx.get_var("x"), None);
+        assert_eq!(eval("sqrt(9)", &mut ctx), Ok(3.0));
+    }
+
+    #[test]
+    fn input_scale_scales_literals_once() {
+        let mut ctx = Context::new().input_scale(0.01).with_var("x", 50.0);
+        assert_eq!(eval("50", &mut ctx), Ok(0.5));
+        assert_eq!(eval("x", &mut ctx), Ok(50.0));
+        assert_eq!(eval("300 squared", &mut ctx), Ok(9.0));
+        assert_eq!(eval("300^2", &mut ctx), Ok(9.0));
+
+        let node = Parser::new("50+50").unwrap().parse().unwrap();
+        assert_eq!(memo::eval_memoized(&node, &mut ctx), Ok(1.0));
+        assert_eq!(explain::explain(&node, &ctx), vec!["0.5 + 0.5 = 1"]);
+
+        let node = Parser::new("x + 200*300").unwrap().parse().unwrap();
+        assert_eq!(ast::partial_eval(&node, &mut ctx), Ok(ast::Node::Number(56.0)));
+    }
+
+    #[test]
+    fn nan_policy_decides_calls_without_real_result() {
+        let fails = eval("asin(2)", &mut Context::new());
+        assert!(matches!(fails, Err(EvalError::DomainError { .. })));
+        assert_eq!(eval("sin(1/0)", &mut Context::new()), Err(EvalError::NotANumber("sin".into())));
+
+        let mut ctx = Context::new().nan_policy(NanPolicy::Propagate);
+        assert!(eval("asin(2) + 1", &mut ctx).unwrap().is_nan());
+
+        let mut ctx = Context::new().nan_policy(NanPolicy::Default(0.0));
+        assert_eq!(eval("asin(2) + 1", &mut ctx), Ok(1.0));
+        assert_eq!(eval("asin(1)", &mut ctx), Ok(std::f64::consts::FRAC_PI_2));
+    }
+
+    #[test]
+    fn self_referential_functions_stop_at_the_recursion_limit() {
+        let mut ctx = Context::new().max_function_recursion(20)
+            .with_func("f", Arity::Exact(1), |args| endless(args, DEFAULT_RECURSION_LIMIT, Rc::default()));
+        assert_eq!(eval("f(0)", &mut ctx), Err(EvalError::RecursionLimit("f".into())));
+        // The depth is restored after the failure
+        assert_eq!(eval("sqrt(4)", &mut ctx), Ok(2.0));
+
+        let mut shallow = Context::new().max_function_recursion(1);
+        assert_eq!(eval("sqrt(4)", &mut shallow), Ok(2.0));
+        assert_eq!(eval("sqrt(sqrt(16))", &mut shallow), Ok(2.0));
+    }
+
+    #[test]
+    fn smallest_recursion_limit_in_the_chain_wins() {
+        // Each call evaluates the next one in a context allowing only 5 nested calls
+        let calls = Rc::new(Cell::new(0));
+        let counted = calls.clone();
+        let mut ctx = Context::new()
+            .with_func("f", Arity::Exact(1), move |args| endless(args, 5, counted.clone()));
+        assert_eq!(eval("f(0)", &mut ctx), Err(EvalError::RecursionLimit("f".into())));
+        assert_eq!(calls.get(), 5);
+    }
+
+    #[test]
+    fn comparison_epsilon_makes_equality_approximate() {
+        assert_eq!(eval("0.1 + 0.2 == 0.3", &mut Context::new()), Ok(0.0));
+        let mut ctx = Context::new().comparison_epsilon(1e-9);
+        assert_eq!(eval("0.1 + 0.2 == 0.3", &mut ctx), Ok(1.0));
+        assert_eq!(eval("1 == 1.001", &mut ctx), Ok(0.0));
+        assert_eq!(eval("1/0 == 1/0", &mut ctx), Ok(1.0));
+    }
+
+    #[test]
+    fn inequality_uses_the_comparison_epsilon() {
+        assert_eq!(eval("0.1 + 0.2 != 0.3", &mut Context::new()), Ok(1.0));
+        let mut ctx = Context::new().comparison_epsilon(1e-9);
+        assert_eq!(eval("0.1 + 0.2 != 0.3", &mut ctx), Ok(0.0));
+        assert_eq!(eval("1 != 1.001", &mut ctx), Ok(1.0));
+        assert_eq!(eval("1 + 1 != 2 == 0", &mut ctx), Ok(1.0));
+    }
+}
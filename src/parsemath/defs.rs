@@ -0,0 +1,116 @@
+//! This module extends a Context with definitions written as text, one per line:
+//! constants such as `g = 9.81` and functions such as `double(x) = x*2`.
+//! Everything after `#` is a comment. Definitions can use the ones before them.
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use super::ast::{self, EvalError, Node};
+use super::context::{Context, Function};
+use super::parser::{self, Definition, ParseErr};
+
+#[derive(Debug)]
+pub enum DefsError {
+    Io(io::Error),
+    /// Invalid definition on the 1-based line
+    Parse { line: usize, error: ParseErr },
+    /// Constant whose value failed to evaluate on the 1-based line
+    Eval { line: usize, error: EvalError },
+}
+
+impl fmt::Display for DefsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            self::DefsError::Io(e) => write!(f, "{}", e),
+            self::DefsError::Parse { line, error } => write!(f, "Line {}: {}", line, error),
+            self::DefsError::Eval { line, error } => write!(f, "Line {}: {}", line, error),
+        }
+    }
+}
+
+impl error::Error for DefsError {}
+
+impl From<io::Error> for DefsError {
+    fn from(error: io::Error) -> Self {
+        DefsError::Io(error)
+    }
+}
+
+/// Reads the definitions file into the context. Returns the number of definitions.
+pub fn load_definitions(ctx: &mut Context, path: &str) -> Result<usize, DefsError> {
+    define_all(ctx, &fs::read_to_string(path)?)
+}
+
+/// Adds the definitions of the text to the context, stopping at the first invalid line.
+/// Constants and functions replace built-in ones of the same name.
+pub fn define_all(ctx: &mut Context, text: &str) -> Result<usize, DefsError> {
+    let mut count = 0;
+    for (index, line) in text.lines().enumerate() {
+        let code = line.split('#').next().unwrap_or("");
+        if code.trim().is_empty() {
+            continue;
+        }
+        let line = index + 1;
+        match parser::parse_definition(code).map_err(|error| DefsError::Parse { line, error })? {
+            Definition::Constant(name, value) => {
+                let value = ast::eval_with(&value, ctx).map_err(|error| DefsError::Eval { line, error })?;
+                ctx.set_const(&name, value);
+            }
+            Definition::Function { name, params, body } => define_function(ctx, &name, params, body),
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Registers a function evaluating the body with the arguments bound to the parameters.
+/// The body is evaluated when called, in a child scope of the calling context: it sees the
+/// current variables and functions, parameters shadow variables and constants of the same
+/// name, and its assignments stay local. Recursion is bounded by the recursion limit.
+pub fn define_function(ctx: &mut Context, name: &str, params: Vec<String>, body: Node) {
+    ctx.set_func(name, Function::defined(params, body));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::Parser;
+    use std::env;
+    use std::process;
+
+    fn eval(expr: &str, ctx: &mut Context) -> Result<f64, EvalError> {
+        ast::eval_with(&Parser::new(expr).unwrap().parse().unwrap(), ctx)
+    }
+
+    #[test]
+    fn loads_a_definitions_file() {
+        let path = env::temp_dir().join(format!("rcalc-defs-{}.txt", process::id()));
+        fs::write(&path, "# physics\ng = 9.81\n\ndouble(x) = x*2  # twice\nfall(t) = g * t^2 / 2\n").unwrap();
+        let mut ctx = Context::new();
+        let count = load_definitions(&mut ctx, path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(eval("double(g)", &mut ctx), Ok(19.62));
+        assert_eq!(eval("fall(2)", &mut ctx), Ok(19.62));
+        assert_eq!(eval("g = 1", &mut ctx), Err(EvalError::ConstantAssignment("g".into())));
+    }
+
+    #[test]
+    fn reports_the_failing_line() {
+        let mut ctx = Context::new();
+        let error = define_all(&mut ctx, "a = 1\nb = a +\n").unwrap_err();
+        assert!(matches!(error, DefsError::Parse { line: 2, .. }), "{}", error);
+        let error = define_all(&mut ctx, "\nc = d + 1").unwrap_err();
+        assert_eq!(error.to_string(), "Line 2: Unbound variable d");
+        assert!(matches!(load_definitions(&mut ctx, "/nonexistent/defs.txt"), Err(DefsError::Io(_))));
+    }
+
+    #[test]
+    fn functions_replace_builtins_and_recurse() {
+        let mut ctx = Context::new();
+        define_all(&mut ctx, "sqrt(x) = x\nfact(n) = n * fact(n - 1)").unwrap();
+        assert_eq!(eval("sqrt(9)", &mut ctx), Ok(9.0));
+        assert!(matches!(eval("fact(3)", &mut ctx), Err(EvalError::RecursionLimit(_))));
+    }
+}
@@ -0,0 +1,250 @@
+//! This module checks an expression for every problem at once, for editors and other tooling:
+//! unreadable characters, syntax errors, unknown names, evaluation errors and warnings.
+use std::fmt;
+use std::ops::Range;
+
+use super::ast::{self, Node};
+use super::context::Context;
+use super::parser::{ParseErr, PushParser};
+use super::token::Token;
+use super::tokenizer::Tokenizer;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// Problem found in the expression, with the byte range it concerns when known
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Range<usize>>,
+}
+
+impl Diagnostic {
+    fn error(message: String, span: Option<Range<usize>>) -> Self {
+        Diagnostic { severity: Severity::Error, message, span }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        match &self.span {
+            Some(span) => write!(f, "{} at {}..{}: {}", severity, span.start, span.end, self.message),
+            None => write!(f, "{}: {}", severity, self.message),
+        }
+    }
+}
+
+/// Result of checking an expression: its value, if it could be evaluated, and all problems found
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub value: Option<f64>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Report {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|d| d.severity == Severity::Error)
+    }
+}
+
+/// Checks the expression without stopping at the first problem. Unreadable characters and
+/// tokens that can not continue the expression are reported and skipped, every unknown variable
+/// and function is reported, and the expression is only evaluated when no error was found,
+/// adding evaluation warnings. Evaluation uses a copy of the context, so assignments are undone.
+pub fn diagnose(expr: &str, ctx: &Context) -> Report {
+    let mut diagnostics = Vec::new();
+    let mut tokens = Vec::new();
+    let mut tokenizer = Tokenizer::new(expr);
+    loop {
+        match tokenizer.next() {
+            Some(Token::EOF) => break,
+            Some(token) => tokens.push((token, tokenizer.position()..tokenizer.end_position())),
+            None => {
+                let position = tokenizer.position();
+                let ch = expr[position..].chars().next().unwrap_or_default();
+                diagnostics.push(Diagnostic::error(
+                    format!("invalid character '{}'", ch),
+                    Some(position..position + ch.len_utf8())));
+            }
+        }
+    }
+
+    // A syntax error is reported at the token where parsing failed, or for a malformed argument
+    // at the argument. The parser discards the failing token, so parsing goes on after it and
+    // finds further errors. Positions of pushed tokens count the tokens the parser took.
+    let mut parser = PushParser::new();
+    let mut taken = Vec::new();
+    let mut node = None;
+    for index in 0..=tokens.len() {
+        let token = tokens.get(index).map_or(Token::EOF, |(token, _)| token.clone());
+        match parser.feed(token) {
+            Ok(Some(parsed)) => node = Some(parsed),
+            Ok(None) => taken.push(index),
+            Err(error) => {
+                let index = match &error {
+                    ParseErr::InvalidArgument { position, .. } => taken.get(*position).copied().unwrap_or(index),
+                    _ => index,
+                };
+                let span = tokens.get(index).map(|(_, span)| span.clone());
+                diagnostics.push(Diagnostic::error(error.to_string(), span));
+            }
+        }
+    }
+
+    let node = match node {
+        Some(node) => node,
+        None => return Report { value: None, diagnostics },
+    };
+    diagnostics.extend(unknown_names(&node, &tokens, ctx));
+    if !diagnostics.is_empty() {
+        return Report { value: None, diagnostics };
+    }
+
+    let mut ctx = ctx.clone();
+    let value = match ast::eval_with(&node, &mut ctx) {
+        Ok(value) => Some(value),
+        Err(error) => {
+            diagnostics.push(Diagnostic::error(error.to_string(), None));
+            None
+        }
+    };
+    diagnostics.extend(ctx.take_warnings().into_iter().map(|warning| Diagnostic {
+        severity: Severity::Warning,
+        message: warning.to_string(),
+        span: None,
+    }));
+    Report { value, diagnostics }
+}
+
+/// Reports each variable and function the context does not know once, at its first use.
+/// Variables assigned in the expression count as known.
+fn unknown_names(node: &Node, tokens: &[(Token, Range<usize>)], ctx: &Context) -> Vec<Diagnostic> {
+    fn collect(
+        node: &Node,
+        variables: &mut Vec<String>,
+        functions: &mut Vec<String>,
+        assigned: &mut Vec<String>,
+    ) {
+        use self::Node::*;
+        match node {
+            Number(_) => {}
+            Variable(name) => variables.push(name.clone()),
+            Assign(name, expr) => {
+                assigned.push(name.clone());
+                collect(expr, variables, functions, assigned);
+            }
+            Add(left, right) | Subtract(left, right) | Multiply(left, right)
+            | Divide(left, right) | Caret(left, right) | Equal(left, right)
+            | NotEqual(left, right) | PercentChange(left, right) => {
+                collect(left, variables, functions, assigned);
+                collect(right, variables, functions, assigned);
+            }
+            Negative(expr) => collect(expr, variables, functions, assigned),
+            Call(name, args) => {
+                functions.push(name.clone());
+                for arg in args {
+                    collect(arg, variables, functions, assigned);
+                }
+            }
+        }
+    }
+
+    let (mut variables, mut functions, mut assigned) = (Vec::new(), Vec::new(), Vec::new());
+    collect(node, &mut variables, &mut functions, &mut assigned);
+    let first_use = |name: &str| tokens.iter()
+        .find(|(token, _)| *token == Token::Ident(name.to_string()))
+        .map(|(_, span)| span.clone());
+
+    let mut diagnostics = Vec::new();
+    let mut reported = Vec::new();
+    for name in variables {
+        if ctx.lookup(&name).is_none() && !assigned.contains(&name) && !reported.contains(&name) {
+            diagnostics.push(Diagnostic::error(format!("unbound variable {}", name), first_use(&name)));
+            reported.push(name);
+        }
+    }
+    for name in functions {
+        if ctx.get_func(&name).is_none() && !reported.contains(&name) {
+            diagnostics.push(Diagnostic::error(format!("unknown function {}", name), first_use(&name)));
+            reported.push(name);
+        }
+    }
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(message: &str, span: Range<usize>) -> Diagnostic {
+        Diagnostic::error(message.to_string(), Some(span))
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let report = diagnose("1 @ + x * foo(2) + x? + 3", &Context::new());
+        assert_eq!(report.value, None);
+        assert_eq!(report.diagnostics, vec![
+            error("invalid character '@'", 2..3),
+            error("invalid character '?'", 20..21),
+            error("unbound variable x", 6..7),
+            error("unknown function foo", 10..13),
+        ]);
+        assert!(report.has_errors());
+    }
+
+    #[test]
+    fn syntax_errors_point_at_the_failing_token() {
+        let report = diagnose("(1 + 2))", &Context::new());
+        assert_eq!(report.diagnostics.len(), 1);
+        assert_eq!(report.diagnostics[0].span, Some(7..8));
+        assert_eq!(report.diagnostics[0].to_string(), "error at 7..8: Error in evaluating Unexpected RightParen");
+
+        let report = diagnose("max(1, , 3)", &Context::new());
+        assert_eq!(report.diagnostics.len(), 1);
+        assert_eq!(report.diagnostics[0].span, Some(7..8));
+    }
+
+    #[test]
+    fn parsing_goes_on_after_a_syntax_error() {
+        let report = diagnose("1 + * 2 ) + x", &Context::new());
+        let spans: Vec<_> = report.diagnostics.iter().map(|d| d.span.clone()).collect();
+        assert_eq!(spans, vec![Some(4..5), Some(8..9), Some(12..13)]);
+        assert_eq!(report.diagnostics[2].message, "unbound variable x");
+
+        let report = diagnose("max(1, , 3) + min(, 2)", &Context::new());
+        let spans: Vec<_> = report.diagnostics.iter().map(|d| d.span.clone()).collect();
+        assert_eq!(spans, vec![Some(7..8), Some(18..19)]);
+    }
+
+    #[test]
+    fn assignments_do_not_change_the_context() {
+        let ctx = Context::new();
+        assert_eq!(diagnose("x = 2", &ctx).value, Some(2.0));
+        assert_eq!(ctx.get_var("x"), None);
+    }
+
+    #[test]
+    fn valid_expressions_are_evaluated_with_warnings() {
+        let report = diagnose("x = 1e20 + 1", &Context::new().precision_warnings(true));
+        assert_eq!(report.value, Some(1e20));
+        assert_eq!(report.diagnostics, vec![Diagnostic {
+            severity: Severity::Warning,
+            message: "precision loss in addition".into(),
+            span: None,
+        }]);
+        assert!(!report.has_errors());
+
+        let report = diagnose("sqrt(-1)", &Context::new());
+        assert_eq!(report.value, None);
+        assert_eq!(report.diagnostics[0].span, None);
+    }
+}
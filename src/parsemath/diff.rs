@@ -0,0 +1,202 @@
+//! This module differentiates AST symbolically, e.g. `x^2 + 3*x` by `x` gives `2*x + 3`.
+//! Results are lightly simplified: terms multiplied by zero vanish and factors of one are dropped.
+use super::ast::{self, EvalError, Node};
+use super::context::Context;
+use super::parser::Parser;
+
+/// Returns the derivative of the node with respect to the variable.
+/// Other variables are treated as constants. Fails for functions without a known derivative,
+/// assignments and comparisons.
+pub fn diff(node: &Node, var: &str) -> Result<Node, EvalError> {
+    use self::Node::*;
+    match node {
+        Number(_) => Ok(Number(0.0)),
+        Variable(name) => Ok(Number(if name == var { 1.0 } else { 0.0 })),
+        Add(u, v) => Ok(add(diff(u, var)?, diff(v, var)?)),
+        Subtract(u, v) => Ok(sub(diff(u, var)?, diff(v, var)?)),
+        Negative(u) => Ok(neg(diff(u, var)?)),
+        // (uv)' = u'v + uv'
+        Multiply(u, v) => Ok(add(
+            mul(diff(u, var)?, (**v).clone()),
+            mul((**u).clone(), diff(v, var)?),
+        )),
+        // (u/v)' = (u'v - uv') / v^2
+        Divide(u, v) => Ok(div(
+            sub(mul(diff(u, var)?, (**v).clone()), mul((**u).clone(), diff(v, var)?)),
+            pow((**v).clone(), Number(2.0)),
+        )),
+        Caret(u, v) if !contains(v, var) => {
+            // (u^n)' = n * u^(n-1) * u'
+            let exponent = match **v {
+                Number(n) => Number(n - 1.0),
+                _ => sub((**v).clone(), Number(1.0)),
+            };
+            Ok(mul(mul((**v).clone(), pow((**u).clone(), exponent)), diff(u, var)?))
+        }
+        // (u^v)' = u^v * (v' * ln(u) + v * u' / u)
+        Caret(u, v) => Ok(mul(
+            node.clone(),
+            add(
+                mul(diff(v, var)?, call("ln", (**u).clone())),
+                div(mul((**v).clone(), diff(u, var)?), (**u).clone()),
+            ),
+        )),
+        Call(name, args) if args.len() == 1 => {
+            let u = args[0].clone();
+            let outer = match name.as_str() {
+                "sin" => call("cos", u.clone()),
+                "cos" => neg(call("sin", u.clone())),
+                "tan" => div(Number(1.0), pow(call("cos", u.clone()), Number(2.0))),
+                "exp" => call("exp", u.clone()),
+                "ln" => div(Number(1.0), u.clone()),
+                "log" => div(Number(1.0), mul(u.clone(), call("ln", Number(10.0)))),
+                "sqrt" => div(Number(1.0), mul(Number(2.0), call("sqrt", u.clone()))),
+                "asin" => div(Number(1.0), call("sqrt", sub(Number(1.0), pow(u.clone(), Number(2.0))))),
+                "acos" => neg(div(Number(1.0), call("sqrt", sub(Number(1.0), pow(u.clone(), Number(2.0)))))),
+                "atan" => div(Number(1.0), add(Number(1.0), pow(u.clone(), Number(2.0)))),
+                _ => return Err(not_differentiable(name)),
+            };
+            Ok(mul(outer, diff(&u, var)?))
+        }
+        // (100 * (v - u) / u)' = 100 * (v/u)'
+        PercentChange(u, v) => Ok(mul(Number(100.0), diff(&div((**v).clone(), (**u).clone()), var)?)),
+        Call(name, _) => Err(not_differentiable(name)),
+        Assign(..) | Equal(..) | NotEqual(..) => Err(EvalError::InvalidArgument(format!(
+            "can not differentiate {}", node))),
+    }
+}
+
+/// Compares the symbolic derivative of the expression at a point with a central finite difference,
+/// returning false if they differ by more than a relative tolerance or either can not be computed.
+/// Useful to validate derivative rules.
+pub fn check_derivative(expr: &str, var: &str, at: f64) -> bool {
+    let node = match Parser::new(expr).and_then(|mut parser| parser.parse()) {
+        Ok(node) => node,
+        Err(_) => return false,
+    };
+    let eval_at = |node: &Node, x: f64| ast::eval_with(node, &mut Context::new().with_var(var, x));
+    let symbolic = match diff(&node, var).and_then(|derivative| eval_at(&derivative, at)) {
+        Ok(value) => value,
+        Err(_) => return false,
+    };
+
+    let h = 1e-5 * at.abs().max(1.0);
+    let numeric = match (eval_at(&node, at + h), eval_at(&node, at - h)) {
+        (Ok(forward), Ok(backward)) => (forward - backward) / (2.0 * h),
+        _ => return false,
+    };
+    (symbolic - numeric).abs() <= 1e-6 * symbolic.abs().max(1.0)
+}
+
+fn not_differentiable(name: &str) -> EvalError {
+    EvalError::InvalidArgument(format!("no derivative known for function {}", name))
+}
+
+/// Whether the variable appears in the node
+fn contains(node: &Node, var: &str) -> bool {
+    use self::Node::*;
+    match node {
+        Number(_) => false,
+        Variable(name) => name == var,
+        Add(left, right) | Subtract(left, right) | Multiply(left, right)
+        | Divide(left, right) | Caret(left, right) | Equal(left, right)
+        | NotEqual(left, right) | PercentChange(left, right) => {
+            contains(left, var) || contains(right, var)
+        }
+        Negative(expr) | Assign(_, expr) => contains(expr, var),
+        Call(_, args) => args.iter().any(|arg| contains(arg, var)),
+    }
+}
+
+fn call(name: &str, arg: Node) -> Node {
+    Node::Call(name.to_string(), vec![arg])
+}
+
+fn add(left: Node, right: Node) -> Node {
+    match (left, right) {
+        (Node::Number(a), Node::Number(b)) => Node::Number(a + b),
+        (Node::Number(0.0), expr) | (expr, Node::Number(0.0)) => expr,
+        (left, right) => Node::Add(Box::new(left), Box::new(right)),
+    }
+}
+
+fn sub(left: Node, right: Node) -> Node {
+    match (left, right) {
+        (Node::Number(a), Node::Number(b)) => Node::Number(a - b),
+        (expr, Node::Number(0.0)) => expr,
+        (Node::Number(0.0), expr) => neg(expr),
+        (left, right) => Node::Subtract(Box::new(left), Box::new(right)),
+    }
+}
+
+fn mul(left: Node, right: Node) -> Node {
+    match (left, right) {
+        (Node::Number(a), Node::Number(b)) => Node::Number(a * b),
+        (Node::Number(0.0), _) | (_, Node::Number(0.0)) => Node::Number(0.0),
+        (Node::Number(1.0), expr) | (expr, Node::Number(1.0)) => expr,
+        (Node::Number(-1.0), expr) | (expr, Node::Number(-1.0)) => neg(expr),
+        (left, right) => Node::Multiply(Box::new(left), Box::new(right)),
+    }
+}
+
+fn div(left: Node, right: Node) -> Node {
+    match (left, right) {
+        (Node::Number(a), Node::Number(b)) if b != 0.0 => Node::Number(a / b),
+        (Node::Number(0.0), _) => Node::Number(0.0),
+        (expr, Node::Number(1.0)) => expr,
+        (left, right) => Node::Divide(Box::new(left), Box::new(right)),
+    }
+}
+
+fn pow(base: Node, exponent: Node) -> Node {
+    match (base, exponent) {
+        (Node::Number(b), Node::Number(e)) if b > 0.0 => Node::Number(b.powf(e)),
+        (base, Node::Number(1.0)) => base,
+        (base, exponent) => Node::Caret(Box::new(base), Box::new(exponent)),
+    }
+}
+
+fn neg(expr: Node) -> Node {
+    match expr {
+        Node::Number(n) => Node::Number(-n),
+        Node::Negative(expr) => *expr,
+        expr => Node::Negative(Box::new(expr)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn derivative(expr: &str) -> String {
+        diff(&Parser::new(expr).unwrap().parse().unwrap(), "x").unwrap().to_string()
+    }
+
+    #[test]
+    fn polynomials_match_finite_differences() {
+        assert!(check_derivative("x^2 + 3*x", "x", 2.0));
+        assert!(check_derivative("4*x^3 - 2*x + 7", "x", -1.5));
+        assert!(check_derivative("5", "x", 3.0));
+    }
+
+    #[test]
+    fn products_and_quotients_match_finite_differences() {
+        assert!(check_derivative("x * sin(x)", "x", 1.0));
+        assert!(check_derivative("(x + 1) * (x - 2)", "x", 0.5));
+        assert!(check_derivative("1 / x", "x", 2.0));
+        assert!(check_derivative("sin(x) / (x^2 + 1)", "x", 0.3));
+    }
+
+    #[test]
+    fn other_variables_are_constants() {
+        assert_eq!(derivative("y * x"), "y");
+        assert_eq!(derivative("y"), "0");
+    }
+
+    #[test]
+    fn fails_without_a_known_derivative() {
+        assert!(!check_derivative("floor(x)", "x", 1.5));
+        assert!(!check_derivative("x +", "x", 1.0));
+        assert!(diff(&Parser::new("x == 1").unwrap().parse().unwrap(), "x").is_err());
+    }
+}
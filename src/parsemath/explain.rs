@@ -0,0 +1,98 @@
+//! This module narrates the evaluation of AST step by step, for teaching purposes.
+use super::ast::{self, EvalError, Node};
+use super::context::Context;
+use super::format::format_result;
+
+/// Lists every operation in evaluation order with its result,
+/// e.g. `["2 * 3 = 6", "1 + 6 = 7"]` for `1 + 2*3`.
+/// Evaluation works on a copy of the context. If it fails, the last step describes the error.
+pub fn explain(node: &Node, ctx: &Context) -> Vec<String> {
+    let mut ctx = ctx.clone();
+    let mut steps = Vec::new();
+    if let Err(e) = explain_node(node, &mut ctx, &mut steps) {
+        steps.push(format!("Error: {}", e));
+    }
+    steps
+}
+
+/// Evaluates the children first, then the operation itself on their values,
+/// so each step gives exactly the result of the regular evaluator
+fn explain_node(node: &Node, ctx: &mut Context, steps: &mut Vec<String>) -> Result<f64, EvalError> {
+    use self::Node::*;
+    let mut binary = |oper: &str, left: &Node, right: &Node, ctx: &mut Context| {
+        let left = explain_node(left, ctx, steps)?;
+        let right = explain_node(right, ctx, steps)?;
+        let value = ast::apply(node, &[left, right], ctx)?;
+        steps.push(format!("{} {} {} = {}",
+            format_result(left), oper, format_result(right), format_result(value)));
+        Ok(value)
+    };
+    match node {
+        Number(_) | Variable(_) => ast::eval_with(node, ctx),
+        Add(left, right) => binary("+", left, right, ctx),
+        Subtract(left, right) => binary("-", left, right, ctx),
+        Multiply(left, right) => binary("*", left, right, ctx),
+        Divide(left, right) => binary("/", left, right, ctx),
+        Caret(base, exponent) => {
+            let base = explain_node(base, ctx, steps)?;
+            let exponent = ctx.unscaled(|ctx| explain_node(exponent, ctx, steps))?;
+            let value = ast::apply(node, &[base, exponent], ctx)?;
+            steps.push(format!("{} ^ {} = {}",
+                format_result(base), format_result(exponent), format_result(value)));
+            Ok(value)
+        }
+        Equal(left, right) => binary("==", left, right, ctx),
+        NotEqual(left, right) => binary("!=", left, right, ctx),
+        PercentChange(from, to) => {
+            let from = explain_node(from, ctx, steps)?;
+            let to = explain_node(to, ctx, steps)?;
+            let value = ast::apply(node, &[from, to], ctx)?;
+            steps.push(format!("from {} to {} = {}",
+                format_result(from), format_result(to), format_result(value)));
+            Ok(value)
+        }
+        Negative(expr) => {
+            let operand = explain_node(expr, ctx, steps)?;
+            let value = -operand;
+            steps.push(format!("-({}) = {}", format_result(operand), format_result(value)));
+            Ok(value)
+        }
+        Call(name, args) => {
+            let args = args.iter()
+                .map(|arg| explain_node(arg, ctx, steps))
+                .collect::<Result<Vec<f64>, EvalError>>()?;
+            let value = ast::apply(node, &args, ctx)?;
+            steps.push(format!("{}({}) = {}", name,
+                args.iter().map(|&n| format_result(n)).collect::<Vec<String>>().join(", "),
+                format_result(value)));
+            Ok(value)
+        }
+        Assign(name, expr) => {
+            let value = explain_node(expr, ctx, steps)?;
+            ctx.set_var(name, value)?;
+            steps.push(format!("{} = {}", name, format_result(value)));
+            Ok(value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::Parser;
+
+    fn steps(expr: &str) -> Vec<String> {
+        explain(&Parser::new(expr).unwrap().parse().unwrap(), &Context::new())
+    }
+
+    #[test]
+    fn lists_steps_in_evaluation_order() {
+        assert_eq!(steps("1 + 2*3"), vec!["2 * 3 = 6", "1 + 6 = 7"]);
+        assert_eq!(steps("x = max(1, 4) - -2"), vec!["max(1, 4) = 4", "-(2) = -2", "4 - -2 = 6", "x = 6"]);
+    }
+
+    #[test]
+    fn ends_with_the_error() {
+        assert_eq!(steps("1 + sqrt(-1)"), vec!["-(1) = -1", "Error: sqrt domain error: argument -1 must not be negative"]);
+    }
+}
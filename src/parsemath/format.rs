@@ -0,0 +1,214 @@
+//! This module converts computed values into text for display.
+use std::env;
+use std::error;
+use std::fmt;
+
+/// Number of decimal places shown by default
+pub const DEFAULT_PRECISION: usize = 10;
+
+/// Largest number of decimal places accepted from the environment
+pub const MAX_PRECISION: usize = 30;
+
+/// Environment variable read by `FormatOpts::from_env`
+pub const PRECISION_VAR: &str = "RCALC_PRECISION";
+
+/// Options controlling how results are displayed
+#[derive(Clone, Debug, PartialEq)]
+pub struct FormatOpts {
+    precision: usize,
+    group_separator: Option<char>,
+    fixed: bool,
+}
+
+impl Default for FormatOpts {
+    fn default() -> Self {
+        FormatOpts {
+            precision: DEFAULT_PRECISION,
+            group_separator: None,
+            fixed: false,
+        }
+    }
+}
+
+impl FormatOpts {
+    pub fn new() -> Self {
+        FormatOpts::default()
+    }
+
+    /// Builds options from the environment: `RCALC_PRECISION` sets the number of decimal places,
+    /// between 0 and `MAX_PRECISION`. Unset or empty variables keep the defaults.
+    pub fn from_env() -> Result<Self, FormatError> {
+        match env::var(PRECISION_VAR) {
+            Ok(value) if !value.trim().is_empty() => Ok(FormatOpts::new().with_precision(parse_precision(&value)?)),
+            Ok(_) | Err(env::VarError::NotPresent) => Ok(FormatOpts::new()),
+            Err(env::VarError::NotUnicode(value)) => Err(FormatError::InvalidPrecision(
+                value.to_string_lossy().into_owned())),
+        }
+    }
+
+    /// Always shows exactly `precision` decimal places, e.g. `0.50` for precision 2,
+    /// without trimming zeros or switching to scientific notation
+    pub fn fixed(precision: usize) -> Self {
+        FormatOpts { precision, fixed: true, ..FormatOpts::default() }
+    }
+
+    /// Sets the maximum number of decimal places
+    pub fn with_precision(mut self, precision: usize) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Groups the integer digits in thousands using the separator, e.g. `1,234,567`
+    pub fn with_grouping(mut self, separator: char) -> Self {
+        self.group_separator = Some(separator);
+        self
+    }
+}
+
+/// Parses a number of decimal places, e.g. from the environment or the command line
+pub fn parse_precision(value: &str) -> Result<usize, FormatError> {
+    match value.trim().parse::<usize>() {
+        Ok(precision) if precision <= MAX_PRECISION => Ok(precision),
+        _ => Err(FormatError::InvalidPrecision(value.to_string())),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FormatError {
+    InvalidPrecision(String),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            self::FormatError::InvalidPrecision(value) => write!(f,
+                "Invalid precision {:?}, expected a whole number from 0 to {}", value, MAX_PRECISION),
+        }
+    }
+}
+
+impl error::Error for FormatError {}
+
+/// Formats a computed value with the default options
+pub fn format_result(value: f64) -> String {
+    format_with(value, &FormatOpts::default())
+}
+
+/// Formats a computed value, trimming trailing zeros of the fixed representation.
+/// Values too small to show up at the fixed precision are printed in scientific notation,
+/// unless the options are `FormatOpts::fixed`. Negative zero is shown as `0`, matching comparisons where `-0.0 == 0.0`.
+pub fn format_with(value: f64, opts: &FormatOpts) -> String {
+    // Collapses -0.0 into 0.0
+    let value = if value == 0.0 { 0.0 } else { value };
+    if !value.is_finite() {
+        return value.to_string();
+    }
+
+    let smallest_shown = 0.5 * 10f64.powi(-(opts.precision as i32));
+    if !opts.fixed && value != 0.0 && value.abs() < smallest_shown {
+        return format!("{:e}", value);
+    }
+
+    // The shortest representation avoids the float noise of printing more digits than the value
+    // has, e.g. `1234567.8910000001`, and is only rounded when it has too many decimals
+    let shortest = value.to_string();
+    let decimals = shortest.find('.').map_or(0, |dot| shortest.len() - dot - 1);
+    let fixed = if opts.fixed || decimals > opts.precision {
+        format!("{:.*}", opts.precision, value)
+    } else {
+        shortest
+    };
+    // Tiny negative values round to zero and should not keep the sign
+    let fixed = match fixed.strip_prefix('-') {
+        Some(unsigned) if unsigned.chars().all(|c| c == '0' || c == '.') => unsigned.to_string(),
+        _ => fixed,
+    };
+    let fixed = if !opts.fixed && fixed.contains('.') {
+        fixed.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        fixed
+    };
+
+    match opts.group_separator {
+        Some(separator) => group_digits(&fixed, separator),
+        None => fixed,
+    }
+}
+
+/// Inserts the separator between every three digits of the integer part
+fn group_digits(fixed: &str, separator: char) -> String {
+    let (sign, unsigned) = match fixed.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", fixed),
+    };
+    let (integer, fraction) = match unsigned.find('.') {
+        Some(dot) => unsigned.split_at(dot),
+        None => (unsigned, ""),
+    };
+
+    let mut grouped = String::from(sign);
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push(separator);
+        }
+        grouped.push(digit);
+    }
+    grouped.push_str(fraction);
+    grouped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{ast, parser::Parser};
+
+    #[test]
+    fn tiny_values_are_not_shown_as_zero() {
+        assert_eq!(format_result(1e-20), "1e-20");
+        assert_eq!(format_result(-3e-15), "-3e-15");
+        assert_eq!(format_result(0.0), "0");
+    }
+
+    #[test]
+    fn shows_no_float_noise() {
+        assert_eq!(format_result(1234567.891), "1234567.891");
+        assert_eq!(format_result(0.1 + 0.2), "0.3");
+        assert_eq!(format_result(1.0 / 3.0), "0.3333333333");
+    }
+
+    #[test]
+    fn negative_zero_is_shown_as_zero() {
+        let value = ast::eval(Parser::new("-1 * 0").unwrap().parse().unwrap()).unwrap();
+        assert!(value.is_sign_negative());
+        assert_eq!(value, 0.0);
+        assert_eq!(format_result(value), "0");
+        assert_eq!(format_with(value, &FormatOpts::fixed(2)), "0.00");
+    }
+
+    #[test]
+    fn groups_integer_digits() {
+        let commas = FormatOpts::new().with_grouping(',');
+        assert_eq!(format_with(1234567.0, &commas), "1,234,567");
+        assert_eq!(format_with(123.0, &commas), "123");
+        assert_eq!(format_with(-1234567.0, &commas), "-1,234,567");
+        assert_eq!(format_with(-123456.0, &commas), "-123,456");
+        assert_eq!(format_with(1234.5678, &commas), "1,234.5678");
+        assert_eq!(format_with(1234567.0, &FormatOpts::new().with_grouping(' ')), "1 234 567");
+    }
+
+    /// The only test touching the variable, as tests share the environment
+    #[test]
+    fn precision_from_environment() {
+        env::set_var(PRECISION_VAR, "3");
+        assert_eq!(FormatOpts::from_env(), Ok(FormatOpts::new().with_precision(3)));
+        assert_eq!(format_with(2.0 / 3.0, &FormatOpts::from_env().unwrap()), "0.667");
+        env::set_var(PRECISION_VAR, " ");
+        assert_eq!(FormatOpts::from_env(), Ok(FormatOpts::new()));
+        env::set_var(PRECISION_VAR, "many");
+        assert_eq!(FormatOpts::from_env(), Err(FormatError::InvalidPrecision("many".into())));
+        env::set_var(PRECISION_VAR, "31");
+        assert!(FormatOpts::from_env().is_err());
+        env::remove_var(PRECISION_VAR);
+        assert_eq!(FormatOpts::from_env(), Ok(FormatOpts::new()));
+    }
+}
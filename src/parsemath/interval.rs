@@ -0,0 +1,270 @@
+//! This module evaluates AST with interval arithmetic, available with the `interval` feature.
+//! Every value is a range `[lo, hi]` and operations return a range containing every possible result,
+//! so `sqrt([4, 9])` is `[2, 3]`. Plain numbers are intervals of width zero.
+//! Bounds are computed with the usual round-to-nearest float operations.
+use std::fmt;
+
+use super::ast::{self, EvalError, Node};
+use super::context::{Arity, Context};
+use super::format::format_result;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl Interval {
+    /// Creates an interval, failing when the lower bound exceeds the upper one
+    pub fn new(lo: f64, hi: f64) -> Result<Self, EvalError> {
+        if lo > hi || lo.is_nan() || hi.is_nan() {
+            return Err(EvalError::InvalidArgument(format!(
+                "invalid interval [{}, {}]", format_result(lo), format_result(hi))));
+        }
+        Ok(Interval { lo, hi })
+    }
+
+    pub fn point(value: f64) -> Self {
+        Interval { lo: value, hi: value }
+    }
+
+    pub fn is_point(&self) -> bool {
+        self.lo == self.hi
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        self.lo <= value && value <= self.hi
+    }
+
+    pub fn width(&self) -> f64 {
+        self.hi - self.lo
+    }
+
+    fn add(&self, other: &Interval) -> Interval {
+        Interval { lo: self.lo + other.lo, hi: self.hi + other.hi }
+    }
+
+    fn sub(&self, other: &Interval) -> Interval {
+        Interval { lo: self.lo - other.hi, hi: self.hi - other.lo }
+    }
+
+    /// Signs may flip the order, so every corner product is considered
+    fn mul(&self, other: &Interval) -> Interval {
+        let products = [
+            self.lo * other.lo,
+            self.lo * other.hi,
+            self.hi * other.lo,
+            self.hi * other.hi,
+        ];
+        Interval {
+            lo: products.iter().copied().fold(f64::INFINITY, f64::min),
+            hi: products.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        }
+    }
+
+    /// Fails when the divisor contains zero, as the result would be unbounded
+    fn div(&self, other: &Interval) -> Result<Interval, EvalError> {
+        if other.contains(0.0) {
+            return Err(EvalError::DivisionByZero);
+        }
+        Ok(self.mul(&Interval { lo: 1.0 / other.hi, hi: 1.0 / other.lo }))
+    }
+
+    fn neg(&self) -> Interval {
+        Interval { lo: -self.hi, hi: -self.lo }
+    }
+
+    /// Raises to a point exponent. Even integer powers of intervals around zero start at zero.
+    fn pow(&self, exponent: &Interval) -> Result<Interval, EvalError> {
+        if !exponent.is_point() {
+            return Err(EvalError::InvalidArgument(
+                "exponent of an interval must be a single number".into()));
+        }
+        let n = exponent.lo;
+        let lo = ast::power(self.lo, n)?;
+        let hi = ast::power(self.hi, n)?;
+        if n.fract() == 0.0 && n % 2.0 == 0.0 {
+            if n < 0.0 && self.contains(0.0) {
+                return Err(EvalError::DivisionByZero);
+            }
+            if self.contains(0.0) {
+                return Ok(Interval { lo: 0.0, hi: lo.max(hi) });
+            }
+            return Ok(Interval { lo: lo.min(hi), hi: lo.max(hi) });
+        }
+        if n.fract() == 0.0 {
+            if n < 0.0 && self.contains(0.0) {
+                return Err(EvalError::DivisionByZero);
+            }
+            return Ok(Interval { lo: lo.min(hi), hi: lo.max(hi) });
+        }
+        if self.lo < 0.0 {
+            return Err(EvalError::InvalidArgument(format!(
+                "{} raised to a fractional power", self)));
+        }
+        Ok(Interval { lo: lo.min(hi), hi: lo.max(hi) })
+    }
+
+    /// Applies a nondecreasing function to both bounds
+    fn monotonic(&self, f: fn(f64) -> f64) -> Interval {
+        Interval { lo: f(self.lo), hi: f(self.hi) }
+    }
+
+    fn abs(&self) -> Interval {
+        if self.contains(0.0) {
+            Interval { lo: 0.0, hi: self.lo.abs().max(self.hi.abs()) }
+        } else {
+            let (lo, hi) = (self.lo.abs(), self.hi.abs());
+            Interval { lo: lo.min(hi), hi: lo.max(hi) }
+        }
+    }
+}
+
+/// Displays as `[lo, hi]`, or a single number for intervals of width zero
+impl fmt::Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_point() {
+            write!(f, "{}", format_result(self.lo))
+        } else {
+            write!(f, "[{}, {}]", format_result(self.lo), format_result(self.hi))
+        }
+    }
+}
+
+/// Evaluates the AST over intervals. Function calls without an interval implementation
+/// only accept intervals of width zero.
+pub fn eval_interval(expr: &Node, ctx: &mut Context) -> Result<Interval, EvalError> {
+    use self::Node::*;
+    match expr {
+        Number(n) => Ok(Interval::point(*n * ctx.input_scale_factor())),
+        Variable(name) => ctx.lookup(name)
+            .map(Interval::point)
+            .ok_or_else(|| EvalError::UnboundVariable(name.clone())),
+        Add(expr1, expr2) => {
+            let left = eval_interval(expr1, ctx)?;
+            Ok(left.add(&eval_interval(expr2, ctx)?))
+        }
+        Subtract(expr1, expr2) => {
+            let left = eval_interval(expr1, ctx)?;
+            Ok(left.sub(&eval_interval(expr2, ctx)?))
+        }
+        Multiply(expr1, expr2) => {
+            let left = eval_interval(expr1, ctx)?;
+            Ok(left.mul(&eval_interval(expr2, ctx)?))
+        }
+        Divide(expr1, expr2) => {
+            let left = eval_interval(expr1, ctx)?;
+            left.div(&eval_interval(expr2, ctx)?)
+        }
+        Caret(expr1, expr2) => {
+            let base = eval_interval(expr1, ctx)?;
+            base.pow(&ctx.unscaled(|ctx| eval_interval(expr2, ctx))?)
+        }
+        Negative(expr1) => Ok(eval_interval(expr1, ctx)?.neg()),
+        Call(name, args) => {
+            let args = args.iter()
+                .map(|arg| eval_interval(arg, ctx))
+                .collect::<Result<Vec<Interval>, EvalError>>()?;
+            call_interval(name, &args, ctx)
+        }
+        Assign(name, expr1) => {
+            let value = eval_interval(expr1, ctx)?;
+            if !value.is_point() {
+                return Err(EvalError::InvalidArgument(format!(
+                    "can not store interval {} in variable {}", value, name)));
+            }
+            ctx.set_var(name, value.lo)?;
+            Ok(value)
+        }
+        Equal(expr1, expr2) | NotEqual(expr1, expr2) => {
+            let left = eval_interval(expr1, ctx)?;
+            let right = eval_interval(expr2, ctx)?;
+            if !left.is_point() || !right.is_point() {
+                return Err(EvalError::InvalidArgument(format!(
+                    "can not compare intervals {} and {}", left, right)));
+            }
+            let equal = ctx.approx_eq(left.lo, right.lo) != matches!(expr, NotEqual(..));
+            Ok(Interval::point(if equal { 1.0 } else { 0.0 }))
+        }
+        PercentChange(expr1, expr2) => {
+            let from = eval_interval(expr1, ctx)?;
+            let to = eval_interval(expr2, ctx)?;
+            if from.is_point() && to.is_point() {
+                return Ok(Interval::point(ast::percent_change(from.lo, to.lo)?));
+            }
+            Ok(to.sub(&from).div(&from)?.mul(&Interval::point(100.0)))
+        }
+    }
+}
+
+fn call_interval(name: &str, args: &[Interval], ctx: &mut Context) -> Result<Interval, EvalError> {
+    match (name, args) {
+        ("interval", [lo, hi]) => return Interval::new(lo.lo, hi.hi),
+        ("interval", _) => return Err(EvalError::ArgumentCount {
+            function: name.to_string(),
+            expected: Arity::Exact(2),
+            got: args.len(),
+        }),
+        ("sqrt", [x]) if x.lo >= 0.0 => return Ok(x.monotonic(f64::sqrt)),
+        ("ln", [x]) if x.lo > 0.0 => return Ok(x.monotonic(f64::ln)),
+        ("log", [x]) if x.lo > 0.0 => return Ok(x.monotonic(f64::log10)),
+        ("exp", [x]) => return Ok(x.monotonic(f64::exp)),
+        ("atan", [x]) => return Ok(x.monotonic(f64::atan)),
+        ("floor", [x]) => return Ok(x.monotonic(f64::floor)),
+        ("ceil", [x]) => return Ok(x.monotonic(f64::ceil)),
+        ("abs", [x]) => return Ok(x.abs()),
+        _ => {}
+    }
+
+    let func = ctx.get_func(name).cloned()
+        .ok_or_else(|| EvalError::UnknownFunction(name.to_string()))?;
+    let point_args = args.iter()
+        .map(|x| if x.is_point() {
+            Ok(x.lo)
+        } else {
+            Err(EvalError::InvalidArgument(format!(
+                "{} is not defined for interval {}", name, x)))
+        })
+        .collect::<Result<Vec<f64>, EvalError>>()?;
+    Ok(Interval::point(ctx.call_in_scope(name, &func, &point_args)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::Parser;
+
+    fn eval(expr: &str) -> Result<Interval, EvalError> {
+        eval_interval(&Parser::new(expr).unwrap().parse().unwrap(), &mut Context::new())
+    }
+
+    fn interval(lo: f64, hi: f64) -> Interval {
+        Interval::new(lo, hi).unwrap()
+    }
+
+    #[test]
+    fn adds_bounds() {
+        assert_eq!(eval("[1, 2] + [10, 20]"), Ok(interval(11.0, 22.0)));
+        assert_eq!(eval("[1, 2] - [10, 20]"), Ok(interval(-19.0, -8.0)));
+        assert_eq!(eval("sqrt([4, 9])"), Ok(interval(2.0, 3.0)));
+    }
+
+    #[test]
+    fn multiplies_every_corner() {
+        assert_eq!(eval("[-2, 3] * [4, 5]"), Ok(interval(-10.0, 15.0)));
+        assert_eq!(eval("[-2, 3] * [-5, -4]"), Ok(interval(-15.0, 10.0)));
+        assert_eq!(eval("[-2, -1] * [-3, 4]"), Ok(interval(-8.0, 6.0)));
+    }
+
+    #[test]
+    fn divides_bounds() {
+        assert_eq!(eval("[1, 2] / [4, 8]"), Ok(interval(0.125, 0.5)));
+        assert_eq!(eval("[1, 2] / [-2, -1]"), Ok(interval(-2.0, -0.5)));
+        assert_eq!(eval("1 / [-1, 1]"), Err(EvalError::DivisionByZero));
+    }
+
+    #[test]
+    fn rejects_reversed_bounds() {
+        assert!(matches!(eval("[2, 1]"), Err(EvalError::InvalidArgument(_))));
+    }
+}
@@ -0,0 +1,134 @@
+//! This module evaluates AST reusing the results of structurally identical subtrees,
+//! so `(a+b)*(a+b) + (a+b)` computes `a+b` once.
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use super::ast::{self, EvalError, Node};
+use super::context::Context;
+
+/// Evaluates the expression like `ast::eval_with`, but each distinct subtree only once.
+/// Functions are assumed to be pure. Subtrees containing an assignment are always evaluated,
+/// and an assignment forgets the results computed so far, as variables may have changed.
+pub fn eval_memoized(expr: &Node, ctx: &mut Context) -> Result<f64, EvalError> {
+    let mut memo = Memo { values: HashMap::new() };
+    Ok(memo.eval(expr, ctx)?.0)
+}
+
+/// Key comparing nodes by structure. A NaN literal never equals itself,
+/// so subtrees containing one are just not reused.
+struct Subtree<'a>(&'a Node);
+
+impl PartialEq for Subtree<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for Subtree<'_> {}
+
+impl Hash for Subtree<'_> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state);
+    }
+}
+
+struct Memo<'a> {
+    values: HashMap<Subtree<'a>, f64>,
+}
+
+impl<'a> Memo<'a> {
+    /// Returns the value and whether it may be reused, i.e. the subtree has no assignment
+    fn eval(&mut self, node: &'a Node, ctx: &mut Context) -> Result<(f64, bool), EvalError> {
+        use self::Node::*;
+        if let Some(&value) = self.values.get(&Subtree(node)) {
+            return Ok((value, true));
+        }
+        let (value, reusable) = match node {
+            Number(_) | Variable(_) => return Ok((ast::eval_with(node, ctx)?, true)),
+            Assign(name, expr) => {
+                let (value, _) = self.eval(expr, ctx)?;
+                ctx.set_var(name, value)?;
+                self.values.clear();
+                return Ok((value, false));
+            }
+            Caret(base, exponent) => {
+                let (base, base_reusable) = self.eval(base, ctx)?;
+                // Exponents are unscaled, so their subtrees get values of their own
+                let (exponent, exponent_reusable) = ctx.unscaled(|ctx| {
+                    Memo { values: HashMap::new() }.eval(exponent, ctx)
+                })?;
+                if !exponent_reusable {
+                    self.values.clear();
+                }
+                (ast::apply(node, &[base, exponent], ctx)?, base_reusable && exponent_reusable)
+            }
+            Add(left, right) | Subtract(left, right) | Multiply(left, right)
+            | Divide(left, right) | Equal(left, right) | NotEqual(left, right)
+            | PercentChange(left, right) => {
+                let (left, left_reusable) = self.eval(left, ctx)?;
+                let (right, right_reusable) = self.eval(right, ctx)?;
+                (ast::apply(node, &[left, right], ctx)?, left_reusable && right_reusable)
+            }
+            Negative(expr) => {
+                let (operand, reusable) = self.eval(expr, ctx)?;
+                (ast::apply(node, &[operand], ctx)?, reusable)
+            }
+            Call(_, args) => {
+                let mut reusable = true;
+                let mut values = Vec::new();
+                for arg in args {
+                    let (value, arg_reusable) = self.eval(arg, ctx)?;
+                    reusable &= arg_reusable;
+                    values.push(value);
+                }
+                (ast::apply(node, &values, ctx)?, reusable)
+            }
+        };
+        if reusable {
+            self.values.insert(Subtree(node), value);
+        }
+        Ok((value, reusable))
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::context::Arity;
+    use super::super::parser::Parser;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    fn parse(expr: &str) -> Node {
+        Parser::new(expr).unwrap().parse().unwrap()
+    }
+
+    /// Context with `f(x) = x + 1` counting its calls
+    fn counting_context() -> (Context, Rc<Cell<usize>>) {
+        let calls = Rc::new(Cell::new(0));
+        let counter = Rc::clone(&calls);
+        let ctx = Context::new().with_var("a", 2.0).with_func("f", Arity::Exact(1), move |args| {
+            counter.set(counter.get() + 1);
+            Ok(args[0] + 1.0)
+        });
+        (ctx, calls)
+    }
+
+    #[test]
+    fn repeated_subtrees_are_evaluated_once() {
+        let (mut ctx, calls) = counting_context();
+        let node = parse("f(a)*f(a) + f(a)");
+        assert_eq!(eval_memoized(&node, &mut ctx), Ok(12.0));
+        assert_eq!(calls.get(), 1);
+        assert_eq!(ast::eval_with(&node, &mut ctx), Ok(12.0));
+        assert_eq!(calls.get(), 4);
+    }
+
+    #[test]
+    fn assignments_forget_results() {
+        let (mut ctx, calls) = counting_context();
+        assert_eq!(eval_memoized(&parse("f(a) + (a = 5) + f(a)"), &mut ctx), Ok(3.0 + 5.0 + 6.0));
+        assert_eq!(calls.get(), 2);
+    }
+}
@@ -1,4 +1,29 @@
 pub mod ast;
+pub mod builtins;
+pub mod cache;
+pub mod calc;
+#[cfg(feature = "complex")]
+pub mod complex;
+pub mod context;
+pub mod defs;
+pub mod diagnostics;
+pub mod diff;
+pub mod explain;
+pub mod format;
+#[cfg(feature = "interval")]
+pub mod interval;
+pub mod memo;
 pub mod parser;
+#[cfg(feature = "rational")]
+pub mod rational;
+pub mod render;
+pub mod rewrite;
+pub mod rpn;
+#[cfg(feature = "session")]
+pub mod session;
+pub mod sexpr;
 pub mod token;
-pub mod tokenizer;
\ No newline at end of file
+pub mod tokenizer;
+#[cfg(feature = "units")]
+pub mod units;
+pub mod value;
\ No newline at end of file
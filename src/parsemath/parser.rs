@@ -1,155 +1,727 @@
-/// This module reads tokens returned by Tokenizer and converts them into AST.
+//! This module reads tokens returned by Tokenizer and converts them into AST.
+//! Expressions are parsed by `ExprParser`, a state machine taking one token at a time,
+//! which `Parser` feeds from a tokenizer and `PushParser` from the caller.
 
-use super::{tokenizer::Tokenizer, token::{Token, OperPrec}, ast::Node};
+use super::{tokenizer::Tokenizer, token::{Token, OperPrec}, ast::{Node, Warning}};
 use std::fmt;
+use std::mem;
 
 pub struct Parser<'a> {
     tokenizer: Tokenizer<'a>,
-    current_token: Token
+    current_token: Token,
+    current_pos: usize,
+    implicit_multiplication: bool,
 }
 
 // Public methods
 impl<'a> Parser<'a> {
     pub fn new(expr: &'a str) -> Result<Self, ParseErr> {
-        let mut tokenizer = Tokenizer::new(expr);
+        Parser::from_tokenizer(Tokenizer::new(expr))
+    }
+
+    /// Creates a parser reading from a configured tokenizer, e.g. one with word operators
+    pub fn from_tokenizer(mut tokenizer: Tokenizer<'a>) -> Result<Self, ParseErr> {
         let current_token = match tokenizer.next() {
             Some(token) => token,
-            None => return Err(ParseErr::InvalidOperator("Invalid character".into()))
+            None => return Err(invalid_character()),
         };
 
         Ok(Parser {
+            current_pos: tokenizer.position(),
             tokenizer,
-            current_token
+            current_token,
+            implicit_multiplication: false,
         })
     }
 
+    /// Reads a name directly followed by another name as a multiplication, e.g. `pi r^2`
+    /// as `pi * r^2`. Off by default, where the missing operator is an error.
+    /// Numbers followed by a name, such as `2x`, are always multiplications.
+    pub fn implicit_multiplication(mut self, enabled: bool) -> Self {
+        self.implicit_multiplication = enabled;
+        self
+    }
+
     /// Generates the AST (the node tree) from the tokens
     pub fn parse(&mut self) -> Result<Node, ParseErr> {
-        let ast = self.generate_ast(OperPrec::DefaultZero);
-        match ast {
-            Ok(ast) => Ok(ast),
-            Err(e) => Err(e),
+        let ast = self.parse_expression()?;
+        self.check_end()?;
+        Ok(ast)
+    }
+}
+
+/// Parser driven by the caller, which feeds the tokens one at a time as they arrive,
+/// e.g. from a stream. The state of the expression is kept between feeds, so every token
+/// is parsed once as it is fed and the token that makes the expression invalid fails right away.
+/// Only after such an error the tokens before it are parsed again, to continue without it.
+/// Positions in errors are token indexes instead of byte offsets.
+#[derive(Debug, Default)]
+pub struct PushParser {
+    expr: ExprParser,
+    /// Tokens of the expression so far
+    tokens: Vec<Token>,
+}
+
+impl PushParser {
+    pub fn new() -> Self {
+        PushParser::default()
+    }
+
+    /// Takes the next token, returning the tree once `EOF` is fed and leaving the parser empty
+    /// for the next expression. A token that can not continue the expression is an error and is
+    /// discarded, so feeding can go on with another one. An error at `EOF` discards the expression.
+    pub fn feed(&mut self, token: Token) -> Result<Option<Node>, ParseErr> {
+        let result = match self.expr.feed(&token, self.tokens.len()) {
+            Ok(Step::Consumed) => {
+                self.tokens.push(token);
+                return Ok(None);
+            }
+            Ok(Step::Complete(node)) if token == Token::EOF => Ok(node),
+            Ok(Step::Complete(_)) => Err(unexpected_token(&token)),
+            Err(error) => Err(error),
+        };
+
+        if result.is_ok() || token == Token::EOF {
+            *self = PushParser::default();
+        } else {
+            // The state before the failing token is restored from the tokens fed before it
+            self.expr = ExprParser::default();
+            for (index, token) in self.tokens.iter().enumerate() {
+                let step = self.expr.feed(token, index);
+                debug_assert!(matches!(step, Ok(Step::Consumed)), "fed tokens parse again");
+            }
         }
+        result.map(Some)
     }
 }
 
+/// Definition of a constant `g = 9.81` or of a function `double(x) = x*2`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Definition {
+    Constant(String, Node),
+    Function { name: String, params: Vec<String>, body: Node },
+}
+
+/// Parses a single definition, e.g. a line of a definitions file
+pub fn parse_definition(expr: &str) -> Result<Definition, ParseErr> {
+    let mut parser = Parser::new(expr)?;
+    let definition = match parser.parse_expression()? {
+        Node::Assign(name, value) => Definition::Constant(name, *value),
+        call @ Node::Call(..) if parser.current_token == Token::Assign => {
+            let (name, params, body) = parser.parse_function_definition(call)?;
+            Definition::Function { name, params, body }
+        }
+        _ => return Err(ParseErr::UnableToParse(
+            "expected a definition such as g = 9.81 or double(x) = x*2".into())),
+    };
+    parser.check_end()?;
+    Ok(definition)
+}
+
+/// Statement of a program: an expression, or the definition of a function like `f(x) = x^2 + 1`
+#[derive(Clone, Debug, PartialEq)]
+pub enum Statement {
+    Expr(Node),
+    Function { name: String, params: Vec<String>, body: Node },
+}
+
+/// Parses a program of `;` separated statements into one AST per statement.
+/// Empty statements, including a trailing `;`, are skipped. Function definitions are not
+/// expressions and fail here, see `parse_statements`.
+pub fn parse_program(expr: &str) -> Result<Vec<Node>, ParseErr> {
+    parse_statements(expr)?
+        .into_iter()
+        .map(|statement| match statement {
+            Statement::Expr(expr) => Ok(expr),
+            Statement::Function { name, .. } => Err(ParseErr::UnableToParse(format!(
+                "expected an expression, got a definition of function {}", name))),
+        })
+        .collect()
+}
+
+/// Parses a program of `;` separated statements, which may also define functions,
+/// such as `f(x) = x^2 + 1; f(3)`. Empty statements are skipped.
+pub fn parse_statements(expr: &str) -> Result<Vec<Statement>, ParseErr> {
+    let mut parser = Parser::new(expr)?;
+    parser.parse_statements()
+}
+
+/// Parses an expression used as a condition, such as `x == 5`. A top-level assignment
+/// like `x = 5` is still accepted there, but most likely a mistyped comparison, so it is reported.
+pub fn parse_condition(expr: &str) -> Result<(Node, Vec<Warning>), ParseErr> {
+    let node = Parser::new(expr)?.parse()?;
+    let warnings = match &node {
+        Node::Assign(name, _) => vec![Warning::AssignmentInCondition(name.clone())],
+        _ => Vec::new(),
+    };
+    Ok((node, warnings))
+}
+
 // Private methods
 impl<'a> Parser<'a> {
-    /// Main method that constructs AST and is invoked recursively
-    fn generate_ast(&mut self, oper_prec: OperPrec) -> Result<Node, ParseErr> {
-        let mut left_expr = self.parse_number()?;
+    /// Parses statements until the end of expression
+    fn parse_statements(&mut self) -> Result<Vec<Statement>, ParseErr> {
+        let mut statements = Vec::new();
+        loop {
+            match self.current_token {
+                Token::EOF => return Ok(statements),
+                Token::Semicolon => self.get_next_token()?,
+                _ => {
+                    let statement = match self.parse_expression()? {
+                        call @ Node::Call(..) if self.current_token == Token::Assign => {
+                            let (name, params, body) = self.parse_function_definition(call)?;
+                            Statement::Function { name, params, body }
+                        }
+                        expr => Statement::Expr(expr),
+                    };
+                    statements.push(statement);
+                    if self.current_token != Token::Semicolon {
+                        self.check_end()?;
+                    }
+                }
+            }
+        }
+    }
 
-        while oper_prec < self.current_token.get_oper_prec() {
-            if self.current_token == Token::EOF {
-                break;
+    /// Parses either an assignment `name = expr` or a plain expression, feeding the tokens
+    /// to an `ExprParser` up to the first one that does not continue the expression
+    fn parse_expression(&mut self) -> Result<Node, ParseErr> {
+        let mut expr = ExprParser::default().implicit_multiplication(self.implicit_multiplication);
+        loop {
+            match expr.feed(&self.current_token, self.current_pos)? {
+                Step::Consumed => {
+                    if let Err(error) = self.get_next_token() {
+                        return Err(expr.in_argument(error));
+                    }
+                }
+                Step::Complete(node) => return Ok(node),
             }
-            let right_expr = self.convert_token_to_node(
-                left_expr.clone())?;
-            
-            left_expr = right_expr;
         }
+    }
+
+    /// Parses the body after `=` of a function definition whose head `f(x, y)` was read as a call.
+    /// Returns the name, the parameters and the body.
+    fn parse_function_definition(&mut self, head: Node) -> Result<(String, Vec<String>, Node), ParseErr> {
+        let (name, args) = match head {
+            Node::Call(name, args) => (name, args),
+            _ => return Err(ParseErr::UnableToParse("expected a function name".into())),
+        };
+        let mut params = Vec::new();
+        for arg in args {
+            match arg {
+                Node::Variable(param) if !params.contains(&param) => params.push(param),
+                Node::Variable(param) => return Err(ParseErr::UnableToParse(format!(
+                    "parameter {} of {} is repeated", param, name))),
+                _ => return Err(ParseErr::UnableToParse(format!(
+                    "parameters of {} must be names", name))),
+            }
+        }
+        self.get_next_token()?;
+        let body = self.parse_expression()?;
+        Ok((name, params, body))
+    }
 
-        Ok(left_expr)
+    /// Checks that the whole expression was consumed
+    fn check_end(&self) -> Result<(), ParseErr> {
+        match self.current_token {
+            Token::EOF => Ok(()),
+            _ => Err(unexpected_token(&self.current_token)),
+        }
+    }
 
+    /// Retrieves next Token from Tokenizer and sets current_token field
+    fn get_next_token(&mut self) -> Result<(), ParseErr> {
+        let next_token = match self.tokenizer.next() {
+            Some(token) => token,
+            None => return Err(invalid_character()),
+        };
+        self.current_token = next_token;
+        self.current_pos = self.tokenizer.position();
+        Ok(())
     }
+}
 
-    /// Constructs AST node for number, taking into account negative prefixes and parenthesis
-    fn parse_number(&mut self) -> Result<Node, ParseErr> {
-        let token = self.current_token.clone();
-        match token {
-            Token::Substract => {
-                self.get_next_token()?;
-                let expr = self.generate_ast(OperPrec::Negative)?;
-                Ok(Node::Negative(Box::new(expr)))
+/// Result of feeding a token to an `ExprParser`
+enum Step {
+    /// The token is part of the expression
+    Consumed,
+    /// The token does not continue the expression, which is complete before it
+    Complete(Node),
+}
+
+/// Start of parsing a node, for the elapsed time reported with the `tracing` feature
+#[derive(Clone, Copy, Debug)]
+struct Started {
+    #[cfg(feature = "tracing")]
+    at: std::time::Instant,
+}
+
+impl Started {
+    fn now() -> Self {
+        Started {
+            #[cfg(feature = "tracing")]
+            at: std::time::Instant::now(),
+        }
+    }
+}
+
+/// Parsing in progress that waits for a node, innermost last on the stack of an `ExprParser`
+#[derive(Debug)]
+enum Frame {
+    /// Operator loop of an expression, taking the operators that bind tighter than `prec`
+    Operators { prec: OperPrec, started: Started },
+    /// Binary operator waiting for its right operand
+    Binary { left: Node, build: fn(Box<Node>, Box<Node>) -> Node },
+    Negative,
+    Assign { name: String, started: Started },
+    /// Parenthesized expression, closed by `)`
+    Paren,
+    /// Parenthesized expression directly followed by another one, as in `(1)(2)`
+    ParenProduct(Node),
+    /// Comma separated arguments up to the closing token. Errors inside an argument
+    /// (`in_arg`) are reported with its index and `position`.
+    Args { function: String, closing: Token, args: Vec<Node>, position: usize, in_arg: bool },
+    /// Percent change `from X to Y`
+    PercentFrom,
+    PercentTo(Node),
+}
+
+/// What the next token is expected to be
+#[derive(Debug, Default)]
+enum Expect {
+    /// Start of either an assignment `name = expr` or a plain expression
+    #[default]
+    Expression,
+    /// Name at the start of an expression, assigned to if `=` follows
+    AssignOrName(String),
+    /// Number, name, prefix `-`, parenthesis and so on
+    Operand,
+    /// Name that is called if `(` follows
+    Name(String),
+    /// Number that starts a mixed number such as `1 1/2` if another number follows
+    Number(f64),
+    MixedDivide { whole: f64, numerator: f64 },
+    MixedDenominator { whole: f64, numerator: f64 },
+    CloseParen(Node),
+    AfterParen(Node),
+    FirstArg,
+    Arg,
+    /// Comma or closing token after an argument
+    ArgEnd(Node),
+    To(Node),
+    /// Operator continuing the operand of the innermost operator loop
+    Operator(Node),
+}
+
+/// How parsing goes on after a state handled the token
+enum Next {
+    Consumed,
+    /// The token is handled again in the new state
+    Again,
+    Complete(Node),
+}
+
+/// Parses a single expression from tokens fed one at a time. The partially parsed expression
+/// is kept as a stack of frames, so every token is looked at once. Assignment has the lowest
+/// precedence and is right associative, so `x = y = 5` is `x = (y = 5)`.
+#[derive(Debug, Default)]
+struct ExprParser {
+    frames: Vec<Frame>,
+    expect: Expect,
+    /// Last consumed token, to recognize implicit multiplications
+    previous_token: Option<Token>,
+    /// Position of the token being parsed
+    position: usize,
+    implicit_multiplication: bool,
+}
+
+impl ExprParser {
+    fn implicit_multiplication(mut self, enabled: bool) -> Self {
+        self.implicit_multiplication = enabled;
+        self
+    }
+
+    /// Parses the next token, found at the position
+    fn feed(&mut self, token: &Token, position: usize) -> Result<Step, ParseErr> {
+        self.position = position;
+        loop {
+            match self.step(token) {
+                Ok(Next::Consumed) => {
+                    self.previous_token = Some(token.clone());
+                    return Ok(Step::Consumed);
+                }
+                Ok(Next::Again) => {}
+                Ok(Next::Complete(node)) => return Ok(Step::Complete(node)),
+                Err(error) => return Err(self.in_argument(error)),
+            }
+        }
+    }
+
+    /// Reports an error found inside a function argument as an error of that argument
+    fn in_argument(&self, error: ParseErr) -> ParseErr {
+        let message = match &error {
+            ParseErr::UnableToParse(message) | ParseErr::InvalidOperator(message) => message.clone(),
+            ParseErr::InvalidArgument { .. } => return error,
+        };
+        let frame = self.frames.iter().rev().find(|frame| matches!(frame, Frame::Args { in_arg: true, .. }));
+        match frame {
+            Some(Frame::Args { .. }) => self.argument_error(frame, message),
+            _ => error,
+        }
+    }
+
+    /// Error of the argument parsed in the Args frame
+    fn argument_error(&self, frame: Option<&Frame>, message: String) -> ParseErr {
+        match frame {
+            Some(Frame::Args { function, args, position, .. }) => ParseErr::InvalidArgument {
+                function: function.clone(),
+                index: args.len(),
+                position: *position,
+                message,
             },
-            Token::Num(i) => {
-                self.get_next_token()?;
-                Ok(Node::Number(i))
+            _ => unreachable!("arguments are parsed in an Args frame"),
+        }
+    }
+
+    /// Handles the token in the expected state
+    fn step(&mut self, token: &Token) -> Result<Next, ParseErr> {
+        let next = match mem::take(&mut self.expect) {
+            Expect::Expression => match token {
+                Token::Ident(name) => {
+                    self.expect = Expect::AssignOrName(name.clone());
+                    Next::Consumed
+                }
+                _ => {
+                    self.operators(OperPrec::DefaultZero);
+                    Next::Again
+                }
             },
-            Token::LeftParen => {
-                self.get_next_token()?;
-                let expr = self.generate_ast(OperPrec::DefaultZero)?;
-                self.check_paren(Token::RightParen)?;
-                if self.current_token == Token::LeftParen {
-                    let right = self.generate_ast(OperPrec::MulDiv)?;
-                    return Ok(Node::Multiply(Box::new(expr), Box::new(right)));
+            Expect::AssignOrName(name) => {
+                if *token == Token::Assign {
+                    self.frames.push(Frame::Assign { name, started: Started::now() });
+                    return Ok(Next::Consumed);
+                }
+                self.operators(OperPrec::DefaultZero);
+                self.expect = Expect::Name(name);
+                Next::Again
+            }
+            Expect::Operand => {
+                self.operand(token)?;
+                Next::Consumed
+            }
+            Expect::Name(name) => {
+                if *token == Token::LeftParen {
+                    self.open_args(name, Token::RightParen);
+                    return Ok(Next::Consumed);
+                }
+                self.operand_done(Node::Variable(name));
+                Next::Again
+            }
+            Expect::Number(whole) => match *token {
+                Token::Num(numerator) => {
+                    if whole.fract() != 0.0 || numerator.fract() != 0.0 {
+                        return Err(not_mixed());
+                    }
+                    self.expect = Expect::MixedDivide { whole, numerator };
+                    Next::Consumed
+                }
+                _ => {
+                    self.operand_done(Node::Number(whole));
+                    Next::Again
+                }
+            },
+            Expect::MixedDivide { whole, numerator } => {
+                if *token != Token::Divide {
+                    return Err(not_mixed());
+                }
+                self.expect = Expect::MixedDenominator { whole, numerator };
+                Next::Consumed
+            }
+            Expect::MixedDenominator { whole, numerator } => match *token {
+                Token::Num(denominator) if denominator.fract() == 0.0 => {
+                    let fraction = Node::Divide(Box::new(Node::Number(numerator)), Box::new(Node::Number(denominator)));
+                    self.operand_done(Node::Add(Box::new(Node::Number(whole)), Box::new(fraction)));
+                    Next::Consumed
+                }
+                _ => return Err(not_mixed()),
+            },
+            Expect::CloseParen(expr) => match token {
+                Token::RightParen => {
+                    self.expect = Expect::AfterParen(expr);
+                    Next::Consumed
+                }
+                Token::Comma => return Err(unexpected_comma()),
+                _ => return Err(ParseErr::InvalidOperator(format!(
+                    "Expected {:?}, got {:?}",
+                    Token::RightParen, token
+                ))),
+            },
+            Expect::AfterParen(expr) => {
+                if *token == Token::LeftParen {
+                    self.frames.push(Frame::ParenProduct(expr));
+                    self.operators(OperPrec::MulDiv);
+                } else {
+                    self.operand_done(expr);
+                }
+                Next::Again
+            }
+            Expect::FirstArg => {
+                if self.closes_args(token) {
+                    self.close_args(None);
+                    return Ok(Next::Consumed);
+                }
+                self.expect = Expect::Arg;
+                Next::Again
+            }
+            Expect::Arg => {
+                self.set_arg_start();
+                if self.closes_args(token) || *token == Token::Comma {
+                    return Err(self.argument_error(self.frames.last(), "empty argument".into()));
+                }
+                if let Some(Frame::Args { in_arg, .. }) = self.frames.last_mut() {
+                    *in_arg = true;
+                }
+                self.expect = Expect::Expression;
+                Next::Again
+            }
+            Expect::ArgEnd(arg) => {
+                if *token == Token::Comma {
+                    if let Some(Frame::Args { args, .. }) = self.frames.last_mut() {
+                        args.push(arg);
+                    }
+                    self.expect = Expect::Arg;
+                } else if self.closes_args(token) {
+                    self.close_args(Some(arg));
+                } else {
+                    let frame = self.frames.last();
+                    let closing = match frame {
+                        Some(Frame::Args { closing, .. }) => closing,
+                        _ => unreachable!("arguments are parsed in an Args frame"),
+                    };
+                    let message = format!("Expected Comma or {:?}, got {:?}", closing, token);
+                    return Err(self.argument_error(frame, message));
+                }
+                Next::Consumed
+            }
+            Expect::To(from) => {
+                if *token != Token::To && !matches!(token, Token::Ident(name) if name == "to") {
+                    return Err(ParseErr::UnableToParse(format!(
+                        "Expected to after from, got {:?}", token)));
                 }
+                self.frames.push(Frame::PercentTo(from));
+                self.operators(OperPrec::Compare);
+                Next::Consumed
+            }
+            Expect::Operator(left) => self.operator(left, token)?,
+        };
+        Ok(next)
+    }
 
-                Ok(expr)
+    /// Starts an operator loop taking the operators that bind tighter than `prec`
+    fn operators(&mut self, prec: OperPrec) {
+        self.frames.push(Frame::Operators { prec, started: Started::now() });
+        self.expect = Expect::Operand;
+    }
+
+    /// Constructs AST node for number, taking into account negative prefixes and parenthesis
+    fn operand(&mut self, token: &Token) -> Result<(), ParseErr> {
+        match token {
+            Token::Substract => {
+                self.frames.push(Frame::Negative);
+                self.operators(OperPrec::Negative);
+            }
+            Token::Num(i) => self.expect = Expect::Number(*i),
+            // Integral floats such as 2.0 are marked to stay floats in mixed integer evaluation
+            Token::Float(f) => {
+                if f.fract() == 0.0 {
+                    self.operand_done(Node::Call("float".into(), vec![Node::Number(*f)]));
+                } else {
+                    self.operand_done(Node::Number(*f));
+                }
+            }
+            Token::Ident(name) => self.expect = Expect::Name(name.clone()),
+            Token::LeftParen => {
+                self.frames.push(Frame::Paren);
+                self.expect = Expect::Expression;
+            }
+            // Interval literal [lo, hi], evaluated by the interval module
+            #[cfg(feature = "interval")]
+            Token::LeftBracket => self.open_args("interval".into(), Token::RightBracket),
+            // Percent change from X to Y, with from and to read as keywords by the tokenizer option
+            Token::From => {
+                self.frames.push(Frame::PercentFrom);
+                self.operators(OperPrec::Compare);
             }
-            _ => Err(ParseErr::UnableToParse("Unable to parse".to_string())),
+            Token::To => return Err(ParseErr::UnableToParse("to without from".into())),
+            Token::Comma => return Err(unexpected_comma()),
+            Token::Squared | Token::Cubed => return Err(ParseErr::UnableToParse(
+                "squared and cubed are operators and can not be used as names".into())),
+            _ => return Err(ParseErr::UnableToParse("Unable to parse".to_string())),
         }
+        Ok(())
+    }
 
+    /// Continues the innermost operator loop after its operand
+    fn operand_done(&mut self, node: Node) {
+        if let Some(Frame::Operators { started, .. }) = self.frames.last() {
+            parsed(&node, *started, self.position);
+        }
+        self.expect = Expect::Operator(node);
     }
 
-    /// Parses operators and converts to AST
-    fn convert_token_to_node(&mut self, left_expr: Node) -> Result<Node, ParseErr> {
-        match self.current_token {
-            Token::Add => {
-                self.get_next_token()?;
-                let right_expr = self.generate_ast(
-                    OperPrec::AddSub)?;
-                Ok(Node::Add(Box::new(left_expr), Box::new(right_expr)))
+    /// Parses operators and converts to AST, or ends the innermost operator loop at a token
+    /// that does not bind tighter than it
+    fn operator(&mut self, left: Node, token: &Token) -> Result<Next, ParseErr> {
+        let implicit = self.implicit_product(token);
+        let oper_prec = if implicit { OperPrec::ImplicitMul } else { token.get_oper_prec() };
+        let loop_prec = match self.frames.last() {
+            Some(Frame::Operators { prec, .. }) => prec,
+            _ => unreachable!("operands are parsed in an operator loop"),
+        };
+        if *loop_prec >= oper_prec || *token == Token::EOF {
+            return Ok(self.end_operators(left));
+        }
+        if implicit {
+            self.frames.push(Frame::Binary { left, build: Node::Multiply });
+            self.operators(OperPrec::ImplicitMul);
+            return Ok(Next::Again);
+        }
+
+        let build: fn(Box<Node>, Box<Node>) -> Node = match token {
+            Token::Add => Node::Add,
+            Token::Substract => Node::Subtract,
+            Token::Multiply => Node::Multiply,
+            Token::Divide => Node::Divide,
+            Token::Caret => Node::Caret,
+            Token::Equal => Node::Equal,
+            Token::NotEqual => Node::NotEqual,
+            Token::Squared | Token::Cubed => {
+                let exponent = if *token == Token::Squared { 2.0 } else { 3.0 };
+                self.operand_done(Node::Caret(Box::new(left), Box::new(Node::Number(exponent))));
+                return Ok(Next::Consumed);
             }
-            Token::Substract => {
-                self.get_next_token()?;
-                let right_expr = self.generate_ast(
-                    OperPrec::AddSub)?;
-                Ok(Node::Subtract(Box::new(left_expr), Box::new(right_expr)))
-            }
-            Token::Multiply => {
-                self.get_next_token()?;
-                let right_expr = self.generate_ast(
-                    OperPrec::MulDiv)?;
-                Ok(Node::Multiply(Box::new(left_expr), Box::new(right_expr)))
-            }
-            Token::Divide => {
-                self.get_next_token()?;
-                let right_expr = self.generate_ast(
-                    OperPrec::MulDiv)?;
-                Ok(Node::Divide(Box::new(left_expr), Box::new(right_expr)))
-            }
-            Token::Caret => {
-                self.get_next_token()?;
-                let right_expr = self.generate_ast(
-                    OperPrec::Power)?;
-                Ok(Node::Caret(Box::new(left_expr), Box::new(right_expr)))
-            }
-            _ => Err(ParseErr::InvalidOperator(format!(
+            _ => return Err(ParseErr::InvalidOperator(format!(
                 "Please enter valid operator {:?}",
-                self.current_token
-            )))
+                token
+            ))),
+        };
+        self.frames.push(Frame::Binary { left, build });
+        self.operators(oper_prec);
+        Ok(Next::Consumed)
+    }
+
+    /// Whether the token starts an implicit multiplication: a name directly after
+    /// a number, e.g. 2x or 4i, or after another name if enabled. Names are read whole,
+    /// so sin is never split into s*i*n.
+    fn implicit_product(&self, token: &Token) -> bool {
+        matches!(token, Token::Ident(_)) && match self.previous_token {
+            Some(Token::Num(_) | Token::Float(_)) => true,
+            Some(Token::Ident(_)) => self.implicit_multiplication,
+            _ => false,
         }
     }
 
-    /// Checks for matching parenthesis in expression
-    fn check_paren(&mut self, expected: Token) -> Result<(), ParseErr> {
-        if expected == self.current_token {
-            self.get_next_token()?;
-            Ok(())
-        } else {
-            Err(ParseErr::InvalidOperator(format!(
-                "Expected {:?}, got {:?}",
-                expected, self.current_token
-            )))
+    /// Ends the innermost operator loop with its operand, handing it to the frame below
+    fn end_operators(&mut self, expr: Node) -> Next {
+        self.frames.pop();
+        match self.frames.pop() {
+            Some(Frame::Binary { left, build }) => self.operand_done(build(Box::new(left), Box::new(expr))),
+            Some(Frame::Negative) => self.operand_done(Node::Negative(Box::new(expr))),
+            Some(Frame::ParenProduct(left)) => self.operand_done(Node::Multiply(Box::new(left), Box::new(expr))),
+            Some(Frame::PercentFrom) => self.expect = Expect::To(expr),
+            Some(Frame::PercentTo(from)) => {
+                self.operand_done(Node::PercentChange(Box::new(from), Box::new(expr)));
+            }
+            frame => return self.expression_done(frame, expr),
         }
+        Next::Again
     }
 
-    /// Retrieves next Token from Tokenizer and sets current_token field
-    fn get_next_token(&mut self) -> Result<(), ParseErr> {
-        let next_token = match self.tokenizer.next() {
-            Some(token) => token,
-            None => return Err(ParseErr::InvalidOperator("Invalid character".into()))
-        };
-        self.current_token = next_token;
-        Ok(())
+    /// Hands a whole expression to the frame that started it
+    fn expression_done(&mut self, frame: Option<Frame>, expr: Node) -> Next {
+        match frame {
+            None => return Next::Complete(expr),
+            Some(Frame::Assign { name, started }) => {
+                let assign = Node::Assign(name, Box::new(expr));
+                parsed(&assign, started, self.position);
+                let frame = self.frames.pop();
+                return self.expression_done(frame, assign);
+            }
+            Some(Frame::Paren) => self.expect = Expect::CloseParen(expr),
+            Some(Frame::Args { function, closing, args, position, .. }) => {
+                self.frames.push(Frame::Args { function, closing, args, position, in_arg: false });
+                self.expect = Expect::ArgEnd(expr);
+            }
+            Some(frame) => unreachable!("{:?} does not take an expression", frame),
+        }
+        Next::Again
+    }
+
+    /// Starts the comma separated arguments of the function up to the closing token
+    fn open_args(&mut self, function: String, closing: Token) {
+        self.frames.push(Frame::Args { function, closing, args: Vec::new(), position: 0, in_arg: false });
+        self.expect = Expect::FirstArg;
+    }
+
+    fn closes_args(&self, token: &Token) -> bool {
+        matches!(self.frames.last(), Some(Frame::Args { closing, .. }) if closing == token)
+    }
+
+    /// Records that the next argument starts at the current position
+    fn set_arg_start(&mut self) {
+        let start = self.position;
+        if let Some(Frame::Args { position, .. }) = self.frames.last_mut() {
+            *position = start;
+        }
+    }
+
+    /// Ends the arguments after the last one, making the call the operand
+    fn close_args(&mut self, last: Option<Node>) {
+        if let Some(Frame::Args { function, mut args, .. }) = self.frames.pop() {
+            args.extend(last);
+            self.operand_done(Node::Call(function, args));
+        }
+    }
+}
+
+/// With the `tracing` feature, reports the node parsed since `started` in a span
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn parsed(node: &Node, started: Started, position: usize) {
+    #[cfg(feature = "tracing")]
+    {
+        let _span = tracing::trace_span!("parse", position).entered();
+        tracing::trace!(node = %node, elapsed_ns = started.at.elapsed().as_nanos() as u64, "parsed");
+    }
+}
+
+/// Commas are only valid between function arguments
+fn unexpected_comma() -> ParseErr {
+    ParseErr::UnableToParse("unexpected ','".into())
+}
+
+fn invalid_character() -> ParseErr {
+    ParseErr::InvalidOperator("Invalid character".into())
+}
+
+/// Error for a token after a complete expression
+fn unexpected_token(token: &Token) -> ParseErr {
+    match token {
+        Token::Comma => unexpected_comma(),
+        _ => ParseErr::InvalidOperator(format!("Unexpected {:?}", token)),
     }
 }
 
+fn not_mixed() -> ParseErr {
+    ParseErr::UnableToParse("mixed numbers are a whole number followed by a fraction, as in 1 1/2".into())
+}
+
 #[derive(Debug)]
 pub enum ParseErr {
     UnableToParse(String),
-    InvalidOperator(String)
+    InvalidOperator(String),
+    /// Malformed function argument; `index` counts from 0 and `position` is its byte offset,
+    /// or its token index with `PushParser`
+    InvalidArgument { function: String, index: usize, position: usize, message: String },
 }
 
 impl fmt::Display for ParseErr {
@@ -159,12 +731,209 @@ impl fmt::Display for ParseErr {
                 "Error in evaluating {}", e),
             self::ParseErr::InvalidOperator(e) => write!(f,
                 "Error in evaluating {}", e),
+            self::ParseErr::InvalidArgument { function, index, position, message } => write!(f,
+                "Error in evaluating argument {} of {} at position {}: {}",
+                index + 1, function, position, message),
         }
     }
 }
 
+impl std::error::Error for ParseErr {}
+
 impl std::convert::From<std::boxed::Box<dyn std::error::Error>> for ParseErr {
     fn from(_evalerror: std::boxed::Box<dyn std::error::Error>) -> Self {
-        return ParseErr::UnableToParse("Unable to parse".into());
+        ParseErr::UnableToParse("Unable to parse".into())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(expr: &str) -> Result<Node, ParseErr> {
+        Parser::new(expr)?.parse()
+    }
+
+    #[test]
+    fn parses_comma_separated_arguments() {
+        assert_eq!(format!("{:?}", parse("max(1, 2)").unwrap()), "Call(\"max\", [Number(1.0), Number(2.0)])");
+    }
+
+    #[test]
+    fn rejects_top_level_comma() {
+        let error = parse("1, 2").unwrap_err();
+        assert_eq!(error.to_string(), "Error in evaluating unexpected ','");
+        assert!(parse("(1, 2)").is_err());
+    }
+
+    #[test]
+    fn parses_program_statements() {
+        let program = parse_program("1 + 2; 3 * 4; 5").unwrap();
+        assert_eq!(format!("{:?}", program),
+            "[Add(Number(1.0), Number(2.0)), Multiply(Number(3.0), Number(4.0)), Number(5.0)]");
+    }
+
+    #[test]
+    fn skips_empty_statements() {
+        assert_eq!(format!("{:?}", parse_program("1;").unwrap()), "[Number(1.0)]");
+        assert_eq!(parse_program(";; 1 ;; 2 ;").unwrap().len(), 2);
+        assert!(parse_program("").unwrap().is_empty());
+        assert!(parse_program(" ; ").unwrap().is_empty());
+        assert!(parse_program("1 + ; 2").is_err());
+    }
+
+    #[test]
+    fn parses_function_definition_statements() {
+        let statements = parse_statements("h(a, b) = a - b; h(3, 1)").unwrap();
+        assert_eq!(statements, vec![
+            Statement::Function {
+                name: "h".into(),
+                params: vec!["a".into(), "b".into()],
+                body: Node::Subtract(Box::new(Node::Variable("a".into())), Box::new(Node::Variable("b".into()))),
+            },
+            Statement::Expr(Node::Call("h".into(), vec![Node::Number(3.0), Node::Number(1.0)])),
+        ]);
+        assert_eq!(
+            parse_program("f(x) = x; 1").unwrap_err().to_string(),
+            "Error in evaluating expected an expression, got a definition of function f");
+    }
+
+    #[test]
+    fn reports_empty_argument() {
+        match parse("max(1, , 3)").unwrap_err() {
+            ParseErr::InvalidArgument { function, index, position, message } => {
+                assert_eq!((function.as_str(), index, position), ("max", 1, 7));
+                assert_eq!(message, "empty argument");
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+    }
+
+    #[test]
+    fn reports_malformed_argument() {
+        match parse("sin(*)").unwrap_err() {
+            ParseErr::InvalidArgument { function, index, position, .. } => {
+                assert_eq!((function.as_str(), index, position), ("sin", 0, 4));
+            }
+            error => panic!("unexpected error {:?}", error),
+        }
+        match parse("max(1, 2 +)").unwrap_err() {
+            ParseErr::InvalidArgument { index, position, .. } => assert_eq!((index, position), (1, 7)),
+            error => panic!("unexpected error {:?}", error),
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn warns_about_assignment_in_condition() {
+        let (node, warnings) = parse_condition("x = 5").unwrap();
+        assert_eq!(node, Node::Assign("x".into(), Box::new(Node::Number(5.0))));
+        assert_eq!(warnings, vec![Warning::AssignmentInCondition("x".into())]);
+        assert_eq!(warnings[0].to_string(), "assignment to x in condition, did you mean '=='?");
+
+        let (_, warnings) = parse_condition("x == 5").unwrap();
+        assert!(warnings.is_empty());
+        // Statements are not conditions
+        assert_eq!(parse_program("x = 5").unwrap(), vec![node]);
+    }
+
+    #[test]
+    fn word_operators_parse_as_symbols() {
+        let words = |expr: &str| Parser::from_tokenizer(Tokenizer::new(expr).word_operators(true))?.parse();
+        assert_eq!(words("3 plus 4").unwrap(), parse("3 + 4").unwrap());
+        assert_eq!(words("10 minus 2").unwrap(), parse("10 - 2").unwrap());
+        assert_eq!(words("5 times 6").unwrap(), parse("5 * 6").unwrap());
+        assert_eq!(words("8 over 2").unwrap(), parse("8 / 2").unwrap());
+        assert_eq!(words("2 plus 3 times 4").unwrap(), parse("2 + 3*4").unwrap());
+        assert_eq!(words("2 plus 3 times 4 == 14").unwrap(), parse("2 + 3*4 == 14").unwrap());
+        // Without the option they are names
+        assert_eq!(parse("times").unwrap(), Node::Variable("times".into()));
+    }
+
+    #[test]
+    fn implicit_multiplication_between_names_is_optional() {
+        let implicit = |expr: &str| Parser::new(expr)?.implicit_multiplication(true).parse();
+        assert_eq!(implicit("pi r^2").unwrap(), parse("pi * r^2").unwrap());
+        assert_eq!(implicit("pi r").unwrap(), parse("pi*r").unwrap());
+        assert_eq!(implicit("2 x y").unwrap(), parse("2 * x * y").unwrap());
+        assert_eq!(implicit("x^2 y").unwrap(), parse("x^2 * y").unwrap());
+        assert_eq!(implicit("sin(0)").unwrap(), Node::Call("sin".into(), vec![Node::Number(0.0)]));
+        assert!(parse("pi r").is_err());
+        assert!(parse("2 x y").is_err());
+    }
+
+    #[test]
+    fn numbers_multiply_following_names() {
+        assert_eq!(parse("2sin(0) == 0").unwrap(), parse("2 * sin(0) == 0").unwrap());
+        assert_eq!(parse("2x").unwrap(), parse("2*x").unwrap());
+        assert_eq!(parse("2^3x").unwrap(), parse("2^3 * x").unwrap());
+        assert_eq!(parse("x^2 y").unwrap(), parse("x^2 * y").unwrap());
+        assert_eq!(parse("1/2x").unwrap(), parse("1/(2*x)").unwrap());
+        assert_eq!(parse("2·x").unwrap(), parse("2*x").unwrap());
+        assert_eq!(parse("3⋅4").unwrap(), parse("3*4").unwrap());
+    }
+
+    /// Feeds the tokens of the expression one at a time, checking that only EOF completes it
+    fn push_parse(expr: &str) -> Result<Node, ParseErr> {
+        let mut tokenizer = Tokenizer::new(expr);
+        let mut parser = PushParser::new();
+        loop {
+            let token = tokenizer.next().expect("valid token");
+            let end = token == Token::EOF;
+            match parser.feed(token)? {
+                Some(node) => return Ok(node),
+                None => assert!(!end, "EOF completes the expression"),
+            }
+        }
+    }
+
+    #[test]
+    fn push_parser_builds_the_same_tree_as_batch_parsing() {
+        for expr in ["1 + 2*3", "-(1 + 2)^2", "x = y = max(1, 2, sin(0))", "2x + 3 squared", "(1)(2) == 2"] {
+            assert_eq!(push_parse(expr).unwrap(), parse(expr).unwrap(), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn push_parser_fails_on_the_causing_token() {
+        let mut parser = PushParser::new();
+        assert!(parser.feed(Token::RightParen).is_err());
+
+        let mut parser = PushParser::new();
+        assert_eq!(parser.feed(Token::Num(1.0)).unwrap(), None);
+        assert_eq!(parser.feed(Token::Add).unwrap(), None);
+        assert!(parser.feed(Token::Multiply).is_err());
+        // The failing token is discarded and the expression goes on
+        assert_eq!(parser.feed(Token::Num(2.0)).unwrap(), None);
+        assert_eq!(parser.feed(Token::EOF).unwrap(), Some(parse("1 + 2").unwrap()));
+    }
+
+    #[test]
+    fn push_parser_keeps_its_state_between_feeds() {
+        // Parsing everything fed so far again for every token would take minutes here
+        let mut parser = PushParser::new();
+        parser.feed(Token::Ident("max".into())).unwrap();
+        parser.feed(Token::LeftParen).unwrap();
+        for _ in 0..20_000 {
+            assert_eq!(parser.feed(Token::Num(1.0)).unwrap(), None);
+            assert_eq!(parser.feed(Token::Comma).unwrap(), None);
+        }
+        parser.feed(Token::Num(2.0)).unwrap();
+        parser.feed(Token::RightParen).unwrap();
+        match parser.feed(Token::EOF).unwrap() {
+            Some(Node::Call(name, args)) => assert_eq!((name.as_str(), args.len()), ("max", 20_001)),
+            node => panic!("unexpected tree {:?}", node),
+        }
+    }
+
+    #[test]
+    fn push_parser_starts_over_after_eof() {
+        let mut parser = PushParser::new();
+        assert_eq!(parser.feed(Token::Num(1.0)).unwrap(), None);
+        assert_eq!(parser.feed(Token::Add).unwrap(), None);
+        assert!(parser.feed(Token::EOF).is_err());
+        assert_eq!(parser.feed(Token::Num(4.0)).unwrap(), None);
+        assert_eq!(parser.feed(Token::EOF).unwrap(), Some(Node::Number(4.0)));
+
+        let error = push_parse("max(1, , 3)").unwrap_err();
+        assert!(matches!(error, ParseErr::InvalidArgument { index: 1, position: 4, .. }), "{:?}", error);
+    }
+}
@@ -0,0 +1,265 @@
+//! This module evaluates AST with exact fractions, available with the `rational` feature.
+//! Integer literals and their sums, differences, products, quotients and integer powers stay exact,
+//! so `1/3 + 1/6` is exactly `1/2`. Anything else falls back to floating point.
+use std::fmt;
+
+use super::ast::{self, EvalError, Node};
+use super::context::Context;
+
+/// Reduced fraction with a positive denominator
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rational {
+    num: i64,
+    den: i64,
+}
+
+impl Rational {
+    /// Creates a reduced fraction, returning None for a zero denominator or on overflow
+    pub fn new(num: i64, den: i64) -> Option<Self> {
+        if den == 0 {
+            return None;
+        }
+        let divisor = gcd(num, den);
+        let (num, den) = (num / divisor, den / divisor);
+        if den < 0 {
+            Some(Rational { num: num.checked_neg()?, den: den.checked_neg()? })
+        } else {
+            Some(Rational { num, den })
+        }
+    }
+
+    pub fn from_integer(n: i64) -> Self {
+        Rational { num: n, den: 1 }
+    }
+
+    /// Converts an integral float that is exactly representable, e.g. a literal like `3`
+    pub fn from_f64(value: f64) -> Option<Self> {
+        // Every integer below 2^53 is exact in f64
+        if value.fract() == 0.0 && value.abs() < 9_007_199_254_740_992.0 {
+            Some(Rational::from_integer(value as i64))
+        } else {
+            None
+        }
+    }
+
+    pub fn numer(&self) -> i64 {
+        self.num
+    }
+
+    pub fn denom(&self) -> i64 {
+        self.den
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    pub fn checked_add(&self, other: &Rational) -> Option<Self> {
+        let num = self.num.checked_mul(other.den)?
+            .checked_add(other.num.checked_mul(self.den)?)?;
+        Rational::new(num, self.den.checked_mul(other.den)?)
+    }
+
+    pub fn checked_sub(&self, other: &Rational) -> Option<Self> {
+        self.checked_add(&other.checked_neg()?)
+    }
+
+    pub fn checked_mul(&self, other: &Rational) -> Option<Self> {
+        Rational::new(self.num.checked_mul(other.num)?, self.den.checked_mul(other.den)?)
+    }
+
+    pub fn checked_div(&self, other: &Rational) -> Option<Self> {
+        Rational::new(self.num.checked_mul(other.den)?, self.den.checked_mul(other.num)?)
+    }
+
+    pub fn checked_neg(&self) -> Option<Self> {
+        Some(Rational { num: self.num.checked_neg()?, den: self.den })
+    }
+
+    /// Raises to an integer power; negative exponents invert the fraction
+    pub fn checked_pow(&self, exponent: i64) -> Option<Self> {
+        let exp = u32::try_from(exponent.checked_abs()?).ok()?;
+        let raised = Rational::new(self.num.checked_pow(exp)?, self.den.checked_pow(exp)?)?;
+        if exponent < 0 {
+            Rational::new(raised.den, raised.num)
+        } else {
+            Some(raised)
+        }
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    let (mut a, mut b) = (a.unsigned_abs(), b.unsigned_abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    // gcd(i64::MIN, 0) does not fit, but dividing by 1 is still correct
+    i64::try_from(a).unwrap_or(1).max(1)
+}
+
+/// Result of rational evaluation: exact when possible, float otherwise
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RationalValue {
+    Exact(Rational),
+    Float(f64),
+}
+
+impl RationalValue {
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            RationalValue::Exact(r) => r.to_f64(),
+            RationalValue::Float(f) => *f,
+        }
+    }
+
+    fn from_f64(value: f64) -> Self {
+        match Rational::from_f64(value) {
+            Some(r) => RationalValue::Exact(r),
+            None => RationalValue::Float(value),
+        }
+    }
+}
+
+impl fmt::Display for RationalValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RationalValue::Exact(r) => write!(f, "{}", r),
+            RationalValue::Float(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Evaluates the AST keeping integer arithmetic exact. Operations overflowing i64,
+/// non integral literals, powers with non integer exponents and function calls produce floats.
+pub fn eval_rational(expr: &Node, ctx: &mut Context) -> Result<RationalValue, EvalError> {
+    use self::Node::*;
+    use self::RationalValue::*;
+    match expr {
+        Number(n) => Ok(RationalValue::from_f64(*n * ctx.input_scale_factor())),
+        Variable(name) => ctx.lookup(name)
+            .map(RationalValue::from_f64)
+            .ok_or_else(|| EvalError::UnboundVariable(name.clone())),
+        Add(expr1, expr2) => binary(expr1, expr2, ctx,
+            Rational::checked_add, |a, b| Ok(a + b)),
+        Subtract(expr1, expr2) => binary(expr1, expr2, ctx,
+            Rational::checked_sub, |a, b| Ok(a - b)),
+        Multiply(expr1, expr2) => binary(expr1, expr2, ctx,
+            Rational::checked_mul, |a, b| Ok(a * b)),
+        Divide(expr1, expr2) => binary(expr1, expr2, ctx,
+            Rational::checked_div, |a, b| Ok(a / b)),
+        Caret(expr1, expr2) => {
+            let base = eval_rational(expr1, ctx)?;
+            let exponent = ctx.unscaled(|ctx| eval_rational(expr2, ctx))?;
+            match (base, exponent) {
+                (Exact(b), Exact(e)) if e.denom() == 1 && !(b.numer() == 0 && e.numer() < 0) => {
+                    match b.checked_pow(e.numer()) {
+                        Some(r) => Ok(Exact(r)),
+                        None => Ok(Float(ast::power(b.to_f64(), e.to_f64())?)),
+                    }
+                }
+                _ => Ok(Float(ast::power(base.to_f64(), exponent.to_f64())?)),
+            }
+        }
+        Negative(expr1) => match eval_rational(expr1, ctx)? {
+            Exact(r) => Ok(r.checked_neg().map_or(Float(-r.to_f64()), Exact)),
+            Float(f) => Ok(Float(-f)),
+        },
+        Call(name, args) => {
+            let func = ctx.get_func(name)
+                .cloned()
+                .ok_or_else(|| EvalError::UnknownFunction(name.clone()))?;
+            let args = args.iter()
+                .map(|arg| eval_rational(arg, ctx).map(|v| v.to_f64()))
+                .collect::<Result<Vec<f64>, EvalError>>()?;
+            Ok(Float(ctx.call_in_scope(name, &func, &args)?))
+        }
+        Assign(name, expr1) => {
+            let value = eval_rational(expr1, ctx)?;
+            ctx.set_var(name, value.to_f64())?;
+            Ok(value)
+        }
+        Equal(expr1, expr2) | NotEqual(expr1, expr2) => {
+            let left = eval_rational(expr1, ctx)?;
+            let right = eval_rational(expr2, ctx)?;
+            let equal = match (left, right) {
+                (Exact(a), Exact(b)) => a == b || ctx.approx_eq(left.to_f64(), right.to_f64()),
+                _ => ctx.approx_eq(left.to_f64(), right.to_f64()),
+            };
+            Ok(Exact(Rational::from_integer((equal != matches!(expr, NotEqual(..))) as i64)))
+        }
+        PercentChange(expr1, expr2) => binary(expr1, expr2, ctx,
+            |from, to| to.checked_sub(from)?.checked_div(from)?.checked_mul(&Rational::from_integer(100)),
+            ast::percent_change),
+    }
+}
+
+/// Applies the exact operation when both operands are exact, otherwise the float one
+fn binary(
+    expr1: &Node,
+    expr2: &Node,
+    ctx: &mut Context,
+    exact: fn(&Rational, &Rational) -> Option<Rational>,
+    float: fn(f64, f64) -> Result<f64, EvalError>,
+) -> Result<RationalValue, EvalError> {
+    let left = eval_rational(expr1, ctx)?;
+    let right = eval_rational(expr2, ctx)?;
+    if let (RationalValue::Exact(a), RationalValue::Exact(b)) = (left, right) {
+        if let Some(r) = exact(&a, &b) {
+            return Ok(RationalValue::Exact(r));
+        }
+    }
+    Ok(RationalValue::Float(float(left.to_f64(), right.to_f64())?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::Parser;
+
+    fn eval(expr: &str) -> RationalValue {
+        eval_rational(&Parser::new(expr).unwrap().parse().unwrap(), &mut Context::new()).unwrap()
+    }
+
+    #[test]
+    fn fractions_stay_exact() {
+        assert_eq!(eval("1/3 + 1/6"), RationalValue::Exact(Rational::new(1, 2).unwrap()));
+        assert_eq!(eval("1/3 + 1/6").to_string(), "1/2");
+        assert_eq!(eval("1/3 + 1/6 == 1/2").to_string(), "1");
+        assert_eq!(eval("(2/3)^-2 * 4").to_string(), "9");
+        assert_eq!(eval("-6/4").to_string(), "-3/2");
+    }
+
+    #[test]
+    fn falls_back_to_float() {
+        assert_eq!(eval("sqrt(4) + 1/2"), RationalValue::Float(2.5));
+        assert_eq!(eval("0.5 + 1"), RationalValue::Float(1.5));
+        assert_eq!(eval("4^(1/2)"), RationalValue::Float(2.0));
+        assert_eq!(eval("2^62 * 4"), RationalValue::Float(2f64.powi(64)));
+    }
+
+    #[test]
+    fn percent_changes_stay_exact() {
+        use super::super::tokenizer::Tokenizer;
+        let eval = |expr: &str| eval_rational(&Parser::from_tokenizer(Tokenizer::new(expr).percent_keywords(true))
+            .unwrap().parse().unwrap(), &mut Context::new());
+        assert_eq!(eval("from 3 to 4").unwrap().to_string(), "100/3");
+        assert_eq!(eval("from 0 to 4").unwrap_err().to_string(),
+            "Invalid argument: percent change from 0 is undefined");
+    }
+
+    #[test]
+    fn reduces_fractions() {
+        assert_eq!(Rational::new(4, -8), Rational::new(-1, 2));
+        assert_eq!(Rational::new(1, 0), None);
+    }
+}
@@ -0,0 +1,221 @@
+//! This module renders AST into human readable text.
+use std::fmt;
+
+use super::ast::Node;
+
+/// Options for infix rendering
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct InfixOpts {
+    /// Shows negative powers as reciprocals, e.g. `x^-1` as `1/x` and `2*x^-2` as `2/x^2`
+    pub reciprocal_powers: bool,
+}
+
+/// Renders the node as an infix expression with the default options
+pub fn to_infix(node: &Node) -> String {
+    to_infix_with(node, &InfixOpts::default())
+}
+
+/// Renders the node as an infix expression that parses back into the same tree,
+/// adding parenthesis only where precedence requires them
+pub fn to_infix_with(node: &Node, opts: &InfixOpts) -> String {
+    if opts.reciprocal_powers {
+        write_infix(&reciprocals(node))
+    } else {
+        write_infix(node)
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", to_infix(self))
+    }
+}
+
+/// Binding strength of the node when rendered, from lowest to highest
+fn precedence(node: &Node) -> u8 {
+    use self::Node::*;
+    match node {
+        Assign(..) => 0,
+        Equal(..) | NotEqual(..) | PercentChange(..) => 1,
+        Add(..) | Subtract(..) => 2,
+        Multiply(..) | Divide(..) => 3,
+        Caret(..) => 4,
+        Negative(..) => 5,
+        Number(n) if n.is_sign_negative() => 5,
+        Number(_) | Variable(_) | Call(..) => 6,
+    }
+}
+
+fn write_infix(node: &Node) -> String {
+    use self::Node::*;
+    match node {
+        Number(n) => n.to_string(),
+        Variable(name) => name.clone(),
+        Add(left, right) => binary(node, left, " + ", right),
+        Subtract(left, right) => binary(node, left, " - ", right),
+        Multiply(left, right) => binary(node, left, "*", right),
+        Divide(left, right) => binary(node, left, "/", right),
+        Caret(left, right) => {
+            // -x^2 would read as -(x^2) although it parses as (-x)^2
+            let base = match precedence(left) {
+                4 | 5 => format!("({})", write_infix(left)),
+                _ => operand(left, 4),
+            };
+            format!("{}^{}", base, operand(right, 5))
+        }
+        Negative(expr) => format!("-{}", operand(expr, 5)),
+        Equal(left, right) => binary(node, left, " == ", right),
+        NotEqual(left, right) => binary(node, left, " != ", right),
+        // Both sides are parsed up to a comparison
+        PercentChange(from, to) => format!("from {} to {}", operand(from, 2), operand(to, 2)),
+        Assign(name, expr) => format!("{} = {}", name, write_infix(expr)),
+        Call(name, args) => format!("{}({})", name, args.iter()
+            .map(write_infix)
+            .collect::<Vec<String>>()
+            .join(", ")),
+    }
+}
+
+/// Operators associate to the left, so only the right operand needs parenthesis at equal precedence
+fn binary(node: &Node, left: &Node, oper: &str, right: &Node) -> String {
+    let prec = precedence(node);
+    format!("{}{}{}", operand(left, prec), oper, operand(right, prec + 1))
+}
+
+/// Renders a child that must bind at least as strongly as `min_prec`
+fn operand(node: &Node, min_prec: u8) -> String {
+    if precedence(node) < min_prec {
+        format!("({})", write_infix(node))
+    } else {
+        write_infix(node)
+    }
+}
+
+/// Rewrites `x^-n` into `1/x^n`, folding a multiplication by it into a division
+fn reciprocals(node: &Node) -> Node {
+    use self::Node::*;
+    let map = |node: &Node| Box::new(reciprocals(node));
+    match node {
+        Caret(base, exponent) => match positive_exponent(exponent) {
+            Some(Number(1.0)) => Divide(Box::new(Number(1.0)), map(base)),
+            Some(exponent) => Divide(Box::new(Number(1.0)), Box::new(Caret(map(base), Box::new(exponent)))),
+            None => Caret(map(base), map(exponent)),
+        },
+        Multiply(left, right) => match reciprocals(right) {
+            Divide(one, denominator) if *one == Number(1.0) => Divide(map(left), denominator),
+            right => Multiply(map(left), Box::new(right)),
+        },
+        Add(left, right) => Add(map(left), map(right)),
+        Subtract(left, right) => Subtract(map(left), map(right)),
+        Divide(left, right) => Divide(map(left), map(right)),
+        Equal(left, right) => Equal(map(left), map(right)),
+        NotEqual(left, right) => NotEqual(map(left), map(right)),
+        PercentChange(from, to) => PercentChange(map(from), map(to)),
+        Negative(expr) => Negative(map(expr)),
+        Assign(name, expr) => Assign(name.clone(), map(expr)),
+        Call(name, args) => Call(name.clone(), args.iter().map(reciprocals).collect()),
+        Number(_) | Variable(_) => node.clone(),
+    }
+}
+
+/// Returns the negated exponent if it is a negative number or a negation
+fn positive_exponent(exponent: &Node) -> Option<Node> {
+    match exponent {
+        Node::Negative(expr) => Some(reciprocals(expr)),
+        Node::Number(n) if *n < 0.0 => Some(Node::Number(-n)),
+        _ => None,
+    }
+}
+
+/// Draws the node as an indented tree, one node per line
+pub fn pretty_tree(node: &Node) -> String {
+    let mut tree = String::new();
+    write_tree(node, "", "", &mut tree);
+    tree
+}
+
+/// Appends the node after its label prefix, and its children after the child prefix
+fn write_tree(node: &Node, label_prefix: &str, child_prefix: &str, tree: &mut String) {
+    tree.push_str(label_prefix);
+    tree.push_str(&label(node));
+    tree.push('\n');
+
+    let children = children(node);
+    for (i, child) in children.iter().enumerate() {
+        if i + 1 == children.len() {
+            write_tree(child, &format!("{}└── ", child_prefix), &format!("{}    ", child_prefix), tree);
+        } else {
+            write_tree(child, &format!("{}├── ", child_prefix), &format!("{}│   ", child_prefix), tree);
+        }
+    }
+}
+
+fn label(node: &Node) -> String {
+    use self::Node::*;
+    match node {
+        Add(..) => "Add".to_string(),
+        Subtract(..) => "Subtract".to_string(),
+        Multiply(..) => "Multiply".to_string(),
+        Divide(..) => "Divide".to_string(),
+        Caret(..) => "Caret".to_string(),
+        Equal(..) => "Equal".to_string(),
+        NotEqual(..) => "NotEqual".to_string(),
+        PercentChange(..) => "PercentChange".to_string(),
+        Negative(..) => "Negative".to_string(),
+        Call(name, _) => format!("Call {}", name),
+        Variable(name) => format!("Variable {}", name),
+        Assign(name, _) => format!("Assign {}", name),
+        Number(n) => format!("Number {}", n),
+    }
+}
+
+fn children(node: &Node) -> Vec<&Node> {
+    use self::Node::*;
+    match node {
+        Add(left, right) | Subtract(left, right) | Multiply(left, right)
+        | Divide(left, right) | Caret(left, right) | Equal(left, right)
+        | NotEqual(left, right) | PercentChange(left, right) => vec![left, right],
+        Negative(expr) | Assign(_, expr) => vec![expr],
+        Call(_, args) => args.iter().collect(),
+        Variable(_) | Number(_) => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::Parser;
+
+    fn parse(expr: &str) -> Node {
+        Parser::new(expr).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn renders_negative_powers_in_both_modes() {
+        let reciprocal = InfixOpts { reciprocal_powers: true };
+        assert_eq!(to_infix(&parse("x^-1")), "x^-1");
+        assert_eq!(to_infix_with(&parse("x^-1"), &reciprocal), "1/x");
+        assert_eq!(to_infix(&parse("2*x^-2")), "2*x^-2");
+        assert_eq!(to_infix_with(&parse("2*x^-2"), &reciprocal), "2/x^2");
+    }
+
+    #[test]
+    fn infix_parses_back_into_the_same_tree() {
+        for expr in ["1 - (2 - 3)", "(1 + 2)*3", "2^3^2", "(-x)^2", "-(x^2)", "x = max(1, y == 2)"] {
+            let tree = parse(expr);
+            assert_eq!(parse(&to_infix(&tree)), tree, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn percent_changes_parse_back_into_the_same_tree() {
+        use super::super::tokenizer::Tokenizer;
+        let parse = |expr: &str| Parser::from_tokenizer(Tokenizer::new(expr).percent_keywords(true))
+            .unwrap().parse().unwrap();
+        for expr in ["from 1 + 2 to 3 == 50", "(from x to y)*2", "from (a == b) to (c = 1)"] {
+            let tree = parse(expr);
+            assert_eq!(parse(&to_infix(&tree)), tree, "{}", expr);
+        }
+        assert_eq!(to_infix(&parse("(from x to y)*2")), "(from x to y)*2");
+    }
+}
@@ -0,0 +1,358 @@
+//! This module contains transformations producing a new AST from an existing one.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+
+use super::ast::{self, Node};
+use super::context::Context;
+use super::parser::{ParseErr, Parser};
+use super::tokenizer::Tokenizer;
+use super::sexpr::to_sexpr;
+
+/// Number of rewrites after which `apply_rules` gives up on reaching a fixed point
+pub const MAX_REWRITES: usize = 10_000;
+
+/// Rebuilds the tree applying `f` to every number literal, keeping structure and names intact
+pub fn map_numbers(node: Node, f: impl Fn(f64) -> f64) -> Node {
+    map_numbers_ref(node, &f)
+}
+
+fn map_numbers_ref(node: Node, f: &dyn Fn(f64) -> f64) -> Node {
+    use self::Node::*;
+    let map = |node: Box<Node>| Box::new(map_numbers_ref(*node, f));
+    match node {
+        Number(n) => Number(f(n)),
+        Variable(name) => Variable(name),
+        Add(left, right) => Add(map(left), map(right)),
+        Subtract(left, right) => Subtract(map(left), map(right)),
+        Multiply(left, right) => Multiply(map(left), map(right)),
+        Divide(left, right) => Divide(map(left), map(right)),
+        Caret(left, right) => Caret(map(left), map(right)),
+        Equal(left, right) => Equal(map(left), map(right)),
+        NotEqual(left, right) => NotEqual(map(left), map(right)),
+        PercentChange(left, right) => PercentChange(map(left), map(right)),
+        Negative(expr) => Negative(map(expr)),
+        Assign(name, expr) => Assign(name, map(expr)),
+        Call(name, args) => Call(name, args.into_iter()
+            .map(|arg| map_numbers_ref(arg, f))
+            .collect()),
+    }
+}
+
+/// Replaces every subtree equal to `pattern` with `replacement`, e.g. each `x*x` with `x^2`.
+/// Matches are searched from the root down, and replacements are not searched again.
+pub fn replace_subtree(node: Node, pattern: &Node, replacement: &Node) -> Node {
+    if node == *pattern {
+        return replacement.clone();
+    }
+    map_children(node, &mut |child| replace_subtree(child, pattern, replacement))
+}
+
+/// Brings the tree into a canonical form, so that `1+x` and `x+1` normalize the same:
+/// chains of `+` and `*` are flattened with their operands sorted and their numbers combined,
+/// operands of `==` and `!=` are sorted, and operations on numbers only are folded into a number.
+/// Function calls are never folded, as functions depend on the context.
+pub fn normalize(node: Node) -> Node {
+    use self::Node::*;
+    let node = map_children(node, &mut normalize);
+    match node {
+        Add(..) => normalize_chain(node, true),
+        Multiply(..) => normalize_chain(node, false),
+        Equal(left, right) if to_sexpr(&right) < to_sexpr(&left) => fold_numbers(Equal(right, left)),
+        NotEqual(left, right) if to_sexpr(&right) < to_sexpr(&left) => fold_numbers(NotEqual(right, left)),
+        node => fold_numbers(node),
+    }
+}
+
+/// Hashes the normalized tree, so that expressions equal after `normalize` hash the same.
+/// The hash is stable between runs of the same build.
+pub fn canonical_hash(node: &Node) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    normalize(node.clone()).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Rebuilds a chain of additions or multiplications with sorted operands and the numbers
+/// combined into a single last operand, dropped when it is 0 for sums or 1 for products
+fn normalize_chain(node: Node, sum: bool) -> Node {
+    let mut operands = Vec::new();
+    collect_chain(node, sum, &mut operands);
+    let (identity, combine): (f64, fn(f64, f64) -> f64) = if sum {
+        (0.0, |a, b| a + b)
+    } else {
+        (1.0, |a, b| a * b)
+    };
+
+    let mut constant = identity;
+    let mut terms = Vec::new();
+    for operand in operands {
+        match operand {
+            Node::Number(n) => constant = combine(constant, n),
+            operand => terms.push(operand),
+        }
+    }
+    terms.sort_by_cached_key(to_sexpr);
+    if terms.is_empty() || constant != identity {
+        terms.push(Node::Number(constant));
+    }
+
+    let mut terms = terms.into_iter();
+    let first = terms.next().unwrap();
+    terms.fold(first, |chain, term| if sum {
+        Node::Add(Box::new(chain), Box::new(term))
+    } else {
+        Node::Multiply(Box::new(chain), Box::new(term))
+    })
+}
+
+fn collect_chain(node: Node, sum: bool, operands: &mut Vec<Node>) {
+    match node {
+        Node::Add(left, right) if sum => {
+            collect_chain(*left, sum, operands);
+            collect_chain(*right, sum, operands);
+        }
+        Node::Multiply(left, right) if !sum => {
+            collect_chain(*left, sum, operands);
+            collect_chain(*right, sum, operands);
+        }
+        node => operands.push(node),
+    }
+}
+
+/// Evaluates an operation whose operands are all numbers, keeping it if that fails
+fn fold_numbers(node: Node) -> Node {
+    use self::Node::*;
+    let constant = match &node {
+        Subtract(left, right) | Divide(left, right) | Caret(left, right) | Equal(left, right)
+        | NotEqual(left, right) | PercentChange(left, right) => {
+            matches!((&**left, &**right), (Number(_), Number(_)))
+        }
+        Negative(expr) => matches!(**expr, Number(_)),
+        _ => false,
+    };
+    if !constant {
+        return node;
+    }
+    match ast::eval_with(&node, &mut Context::empty()) {
+        Ok(value) => Number(value),
+        Err(_) => node,
+    }
+}
+
+/// Rebuilds the node applying `f` to each direct child
+fn map_children(node: Node, f: &mut dyn FnMut(Node) -> Node) -> Node {
+    use self::Node::*;
+    let mut map = |node: Box<Node>| Box::new(f(*node));
+    match node {
+        Number(_) | Variable(_) => node,
+        Add(left, right) => Add(map(left), map(right)),
+        Subtract(left, right) => Subtract(map(left), map(right)),
+        Multiply(left, right) => Multiply(map(left), map(right)),
+        Divide(left, right) => Divide(map(left), map(right)),
+        Caret(left, right) => Caret(map(left), map(right)),
+        Equal(left, right) => Equal(map(left), map(right)),
+        NotEqual(left, right) => NotEqual(map(left), map(right)),
+        PercentChange(left, right) => PercentChange(map(left), map(right)),
+        Negative(expr) => Negative(map(expr)),
+        Assign(name, expr) => Assign(name, map(expr)),
+        Call(name, args) => Call(name, args.into_iter().map(f).collect()),
+    }
+}
+
+/// Rewrite rule such as `?a + 0 -> ?a`. Names starting with `?` are metavariables matching
+/// any subtree; a metavariable used twice in the pattern must match equal subtrees.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pattern: Node,
+    replacement: Node,
+}
+
+impl Rule {
+    /// Creates a rule, failing if the replacement uses a metavariable the pattern does not bind
+    pub fn new(pattern: Node, replacement: Node) -> Result<Self, ParseErr> {
+        let mut bound = Vec::new();
+        metavariables(&pattern, &mut bound);
+        let mut used = Vec::new();
+        metavariables(&replacement, &mut used);
+        if let Some(name) = used.iter().find(|name| !bound.contains(name)) {
+            return Err(ParseErr::UnableToParse(format!(
+                "{} is not bound by the pattern of the rule", name)));
+        }
+        Ok(Rule { pattern, replacement })
+    }
+
+    /// Parses a rule written as `pattern -> replacement`
+    pub fn parse(rule: &str) -> Result<Self, ParseErr> {
+        let (pattern, replacement) = rule.split_once("->")
+            .ok_or_else(|| ParseErr::UnableToParse(format!("Expected -> in rule {}", rule)))?;
+        let parse = |side| Parser::from_tokenizer(Tokenizer::new(side).metavariables(true))?.parse();
+        Rule::new(parse(pattern)?, parse(replacement)?)
+    }
+
+    /// Rewrites the node itself if it matches the pattern, without looking at its children
+    fn apply(&self, node: &Node) -> Option<Node> {
+        let mut bindings = HashMap::new();
+        if matches(&self.pattern, node, &mut bindings) {
+            Some(substitute(&self.replacement, &bindings))
+        } else {
+            None
+        }
+    }
+}
+
+/// Rewrites the tree with the rules until none of them applies anymore.
+/// Each pass rewrites children before their parent, trying the rules in order at every node.
+/// Matching is purely structural: `?a + 0` does not match `0 + x`.
+/// Rules that never settle, such as `?a -> ?a + 0`, stop after `MAX_REWRITES` rewrites.
+pub fn apply_rules(node: Node, rules: &[Rule]) -> Node {
+    let mut node = node;
+    let mut rewrites = 0;
+    loop {
+        let before = rewrites;
+        node = rewrite_pass(node, rules, &mut rewrites);
+        if rewrites == before || rewrites >= MAX_REWRITES {
+            return node;
+        }
+    }
+}
+
+fn rewrite_pass(node: Node, rules: &[Rule], rewrites: &mut usize) -> Node {
+    let node = map_children(node, &mut |child| rewrite_pass(child, rules, rewrites));
+    if *rewrites >= MAX_REWRITES {
+        return node;
+    }
+    match rules.iter().find_map(|rule| rule.apply(&node)) {
+        Some(rewritten) => {
+            *rewrites += 1;
+            rewritten
+        }
+        None => node,
+    }
+}
+
+fn is_metavariable(name: &str) -> bool {
+    name.starts_with('?')
+}
+
+/// Collects the metavariables appearing in the node
+fn metavariables(node: &Node, names: &mut Vec<String>) {
+    use self::Node::*;
+    match node {
+        Variable(name) if is_metavariable(name) => names.push(name.clone()),
+        Number(_) | Variable(_) => {}
+        Add(left, right) | Subtract(left, right) | Multiply(left, right)
+        | Divide(left, right) | Caret(left, right) | Equal(left, right)
+        | NotEqual(left, right) | PercentChange(left, right) => {
+            metavariables(left, names);
+            metavariables(right, names);
+        }
+        Negative(expr) | Assign(_, expr) => metavariables(expr, names),
+        Call(_, args) => args.iter().for_each(|arg| metavariables(arg, names)),
+    }
+}
+
+/// Matches the node against the pattern, recording what each metavariable stands for
+fn matches(pattern: &Node, node: &Node, bindings: &mut HashMap<String, Node>) -> bool {
+    use self::Node::*;
+    match (pattern, node) {
+        (Variable(name), _) if is_metavariable(name) => match bindings.get(name) {
+            Some(bound) => bound == node,
+            None => {
+                bindings.insert(name.clone(), node.clone());
+                true
+            }
+        },
+        (Add(p1, p2), Add(n1, n2))
+        | (Subtract(p1, p2), Subtract(n1, n2))
+        | (Multiply(p1, p2), Multiply(n1, n2))
+        | (Divide(p1, p2), Divide(n1, n2))
+        | (Caret(p1, p2), Caret(n1, n2))
+        | (Equal(p1, p2), Equal(n1, n2))
+        | (NotEqual(p1, p2), NotEqual(n1, n2))
+        | (PercentChange(p1, p2), PercentChange(n1, n2)) => matches(p1, n1, bindings) && matches(p2, n2, bindings),
+        (Negative(p), Negative(n)) => matches(p, n, bindings),
+        (Assign(p_name, p), Assign(n_name, n)) => p_name == n_name && matches(p, n, bindings),
+        (Call(p_name, p_args), Call(n_name, n_args)) => p_name == n_name
+            && p_args.len() == n_args.len()
+            && p_args.iter().zip(n_args).all(|(p, n)| matches(p, n, bindings)),
+        _ => pattern == node,
+    }
+}
+
+/// Replaces the metavariables of the replacement with the subtrees they matched
+fn substitute(replacement: &Node, bindings: &HashMap<String, Node>) -> Node {
+    match replacement {
+        Node::Variable(name) if is_metavariable(name) => bindings[name].clone(),
+        _ => map_children(replacement.clone(), &mut |child| substitute(&child, bindings)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(expr: &str) -> Node {
+        Parser::new(expr).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn map_numbers_doubles_every_literal() {
+        let doubled = map_numbers(parse("1 + x * max(2, -3) ^ y"), |n| n * 2.0);
+        assert_eq!(doubled, parse("2 + x * max(4, -6) ^ y"));
+        assert_eq!(map_numbers(parse("x = y"), |n| n * 2.0), parse("x = y"));
+    }
+
+    #[test]
+    fn replaces_a_single_subtree() {
+        let replaced = replace_subtree(parse("1 + x*x"), &parse("x*x"), &parse("x^2"));
+        assert_eq!(replaced, parse("1 + x^2"));
+        let unchanged = replace_subtree(parse("x*y"), &parse("x*x"), &parse("x^2"));
+        assert_eq!(unchanged, parse("x*y"));
+    }
+
+    #[test]
+    fn replaces_every_occurrence() {
+        let replaced = replace_subtree(parse("x*x - sqrt(x*x) / (x*x)"), &parse("x*x"), &parse("x^2"));
+        assert_eq!(replaced, parse("x^2 - sqrt(x^2) / x^2"));
+    }
+
+    #[test]
+    fn user_rules_reach_a_fixed_point() {
+        let rules = [
+            Rule::parse("?a + 0 -> ?a").unwrap(),
+            Rule::parse("?a * 1 -> ?a").unwrap(),
+            Rule::parse("?a * ?a -> ?a^2").unwrap(),
+        ];
+        assert_eq!(apply_rules(parse("(x * 1 + 0) * (x + 0)"), &rules), parse("x^2"));
+        assert_eq!(apply_rules(parse("0 + x"), &rules), parse("0 + x"));
+        assert_eq!(apply_rules(parse("x * y"), &rules), parse("x * y"));
+    }
+
+    #[test]
+    fn rules_stop_when_they_never_settle() {
+        let rules = [Rule::parse("?a -> ?a + 0").unwrap()];
+        assert!(matches!(apply_rules(parse("x"), &rules), Node::Add(..)));
+    }
+
+    #[test]
+    fn rejects_unbound_metavariables() {
+        assert!(Rule::parse("?a + 0 -> ?b").is_err());
+        assert!(Rule::parse("?a + 0").is_err());
+    }
+
+    #[test]
+    fn equivalent_expressions_normalize_the_same() {
+        assert_eq!(normalize(parse("1 + x")), normalize(parse("x + 1")));
+        assert_eq!(normalize(parse("2 * y * 3 * x")), normalize(parse("x * (y * 6)")));
+        assert_eq!(normalize(parse("x + 0")), parse("x"));
+        assert_eq!(normalize(parse("a == 2 - 1")), normalize(parse("1 == a")));
+    }
+
+    #[test]
+    fn canonical_hash_matches_equivalent_expressions() {
+        assert_eq!(canonical_hash(&parse("1 + x")), canonical_hash(&parse("x + 1")));
+        assert_eq!(canonical_hash(&parse("x * 2 * y")), canonical_hash(&parse("y * (2 * x)")));
+        assert_ne!(canonical_hash(&parse("1 + x")), canonical_hash(&parse("1 - x")));
+        assert_ne!(canonical_hash(&parse("1 - x")), canonical_hash(&parse("x - 1")));
+    }
+}
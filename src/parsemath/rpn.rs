@@ -0,0 +1,139 @@
+//! This module converts AST to and from postfix (RPN) text, e.g. `1+2*3` and `1 2 3 * +`.
+//! Negation is `neg`, a percent change is `50 75 %change`, assignment is `x 5 =` and function
+//! calls carry their argument count, such as `1 2 max(2)`.
+use super::ast::Node;
+use super::parser::ParseErr;
+use super::sexpr::parse_atom;
+
+/// Renders the node in postfix order, with operands before their operator
+pub fn to_postfix_string(node: &Node) -> String {
+    let mut tokens = Vec::new();
+    write_postfix(node, &mut tokens);
+    tokens.join(" ")
+}
+
+fn write_postfix(node: &Node, tokens: &mut Vec<String>) {
+    use self::Node::*;
+    let mut binary = |left: &Node, right: &Node, oper: &str| {
+        write_postfix(left, tokens);
+        write_postfix(right, tokens);
+        tokens.push(oper.to_string());
+    };
+    match node {
+        Number(n) => tokens.push(n.to_string()),
+        Variable(name) => tokens.push(name.clone()),
+        Add(left, right) => binary(left, right, "+"),
+        Subtract(left, right) => binary(left, right, "-"),
+        Multiply(left, right) => binary(left, right, "*"),
+        Divide(left, right) => binary(left, right, "/"),
+        Caret(left, right) => binary(left, right, "^"),
+        Equal(left, right) => binary(left, right, "=="),
+        NotEqual(left, right) => binary(left, right, "!="),
+        PercentChange(left, right) => binary(left, right, "%change"),
+        Negative(expr) => {
+            write_postfix(expr, tokens);
+            tokens.push("neg".to_string());
+        }
+        Assign(name, expr) => {
+            tokens.push(name.clone());
+            write_postfix(expr, tokens);
+            tokens.push("=".to_string());
+        }
+        Call(name, args) => {
+            args.iter().for_each(|arg| write_postfix(arg, tokens));
+            tokens.push(format!("{}({})", name, args.len()));
+        }
+    }
+}
+
+/// Parses whitespace separated postfix text such as `1 2 3 * +` back into AST.
+/// Operators without enough operands and operands left over at the end are errors.
+pub fn from_postfix_string(rpn: &str) -> Result<Node, ParseErr> {
+    let mut stack: Vec<Node> = Vec::new();
+    for (index, token) in rpn.split_whitespace().enumerate() {
+        let mut pop = |count: usize| {
+            if stack.len() < count {
+                return Err(ParseErr::UnableToParse(format!(
+                    "Stack underflow at token {} '{}': expected {} operand(s), found {}",
+                    index + 1, token, count, stack.len())));
+            }
+            Ok(stack.split_off(stack.len() - count))
+        };
+        let node = match token {
+            "+" | "-" | "*" | "/" | "^" | "==" | "!=" | "%change" | "=" => {
+                let mut operands = pop(2)?.into_iter();
+                let left = Box::new(operands.next().unwrap());
+                let right = Box::new(operands.next().unwrap());
+                match token {
+                    "+" => Node::Add(left, right),
+                    "-" => Node::Subtract(left, right),
+                    "*" => Node::Multiply(left, right),
+                    "/" => Node::Divide(left, right),
+                    "^" => Node::Caret(left, right),
+                    "==" => Node::Equal(left, right),
+                    "!=" => Node::NotEqual(left, right),
+                    "%change" => Node::PercentChange(left, right),
+                    _ => match *left {
+                        Node::Variable(name) => Node::Assign(name, right),
+                        _ => return Err(ParseErr::UnableToParse(format!(
+                            "Left side of = at token {} must be a variable", index + 1))),
+                    },
+                }
+            }
+            "neg" => Node::Negative(Box::new(pop(1)?.remove(0))),
+            _ => match parse_call(token) {
+                Some((name, count)) => Node::Call(name.to_string(), pop(count)?),
+                None => parse_atom(token)?,
+            },
+        };
+        stack.push(node);
+    }
+
+    match stack.len() {
+        1 => Ok(stack.remove(0)),
+        0 => Err(ParseErr::UnableToParse("Empty RPN expression".into())),
+        n => Err(ParseErr::UnableToParse(format!(
+            "{} operands left over, expected a single result", n))),
+    }
+}
+
+/// Splits a call token such as `max(2)` into the name and argument count
+fn parse_call(token: &str) -> Option<(&str, usize)> {
+    let (name, count) = token.strip_suffix(')')?.split_once('(')?;
+    Some((name, count.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::Parser;
+
+    fn parse(expr: &str) -> Node {
+        Parser::new(expr).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn renders_operands_before_operators() {
+        assert_eq!(to_postfix_string(&parse("1+2*3")), "1 2 3 * +");
+        assert_eq!(to_postfix_string(&parse("x = -max(1, 2)")), "x 1 2 max(2) neg =");
+    }
+
+    #[test]
+    fn round_trips_through_postfix_text() {
+        for expr in ["1+2*3", "(1+2)*3", "2^3^2", "x = y - 4/2", "max(1, sin(x), 3) == -2"] {
+            let node = parse(expr);
+            assert_eq!(from_postfix_string(&to_postfix_string(&node)).unwrap(), node, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_postfix_text() {
+        let error = |rpn: &str| from_postfix_string(rpn).unwrap_err().to_string();
+        assert_eq!(error("1 +"),
+            "Error in evaluating Stack underflow at token 2 '+': expected 2 operand(s), found 1");
+        assert_eq!(error("1 2 3 +"), "Error in evaluating 2 operands left over, expected a single result");
+        assert_eq!(error(""), "Error in evaluating Empty RPN expression");
+        assert_eq!(error("1 2 ="), "Error in evaluating Left side of = at token 3 must be a variable");
+        assert!(from_postfix_string("1 max(2)").is_err());
+    }
+}
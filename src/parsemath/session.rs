@@ -0,0 +1,138 @@
+//! This module saves the variables of a Context to a JSON file and loads them back,
+//! so a REPL session can continue later, available with the `session` feature.
+//! The file is a flat object such as `{"rate": 0.5, "x": 2.0}`.
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+
+use serde::{Deserialize, Serialize};
+
+use super::ast::EvalError;
+use super::context::Context;
+
+/// Variables by name, serialized as a flat JSON object sorted by name
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+struct Vars(BTreeMap<String, f64>);
+
+#[derive(Debug)]
+pub enum SessionError {
+    Io(io::Error),
+    InvalidJson(String),
+    /// Variable holding infinity or NaN, which JSON can not represent
+    NotFinite(String, f64),
+    Eval(EvalError),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self {
+            self::SessionError::Io(e) => write!(f, "{}", e),
+            self::SessionError::InvalidJson(e) => write!(f, "Invalid session file: {}", e),
+            self::SessionError::NotFinite(name, value) => write!(f,
+                "Can not save variable {} = {}, JSON has no infinity or NaN", name, value),
+            self::SessionError::Eval(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for SessionError {}
+
+impl From<io::Error> for SessionError {
+    fn from(error: io::Error) -> Self {
+        SessionError::Io(error)
+    }
+}
+
+impl From<EvalError> for SessionError {
+    fn from(error: EvalError) -> Self {
+        SessionError::Eval(error)
+    }
+}
+
+/// Writes the variables of the context to the file, replacing it
+pub fn save_vars(ctx: &Context, path: &str) -> Result<(), SessionError> {
+    fs::write(path, vars_to_json(ctx)?)?;
+    Ok(())
+}
+
+/// Sets the variables stored in the file, keeping other variables of the context.
+/// Nothing is set if the file is invalid or names a constant. Returns the number of variables loaded.
+pub fn load_vars(ctx: &mut Context, path: &str) -> Result<usize, SessionError> {
+    let vars = vars_from_json(&fs::read_to_string(path)?)?;
+    if let Some((name, _)) = vars.iter().find(|(name, _)| ctx.get_const(name).is_some()) {
+        return Err(EvalError::ConstantAssignment(name.clone()).into());
+    }
+    for (name, value) in &vars {
+        ctx.set_var(name, *value)?;
+    }
+    Ok(vars.len())
+}
+
+/// Renders the variables as a JSON object, sorted by name.
+/// JSON has no infinity or NaN, so variables holding them can not be saved.
+pub fn vars_to_json(ctx: &Context) -> Result<String, SessionError> {
+    if let Some((name, value)) = ctx.vars().find(|(_, value)| !value.is_finite()) {
+        return Err(SessionError::NotFinite(name.to_string(), value));
+    }
+    let vars = Vars(ctx.vars().map(|(name, value)| (name.to_string(), value)).collect());
+    let mut json = serde_json::to_string_pretty(&vars).map_err(|e| SessionError::InvalidJson(e.to_string()))?;
+    json.push('\n');
+    Ok(json)
+}
+
+/// Parses a JSON object of numbers, as written by `vars_to_json`
+pub fn vars_from_json(json: &str) -> Result<Vec<(String, f64)>, SessionError> {
+    let vars: Vars = serde_json::from_str(json).map_err(|e| SessionError::InvalidJson(e.to_string()))?;
+    Ok(vars.0.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    #[test]
+    fn json_round_trip() {
+        let ctx = Context::new().with_var("x", 2.0).with_var("rate", 0.5).with_var("tiny", 1e-300);
+        let json = vars_to_json(&ctx).unwrap();
+        assert_eq!(json, "{\n  \"rate\": 0.5,\n  \"tiny\": 1e-300,\n  \"x\": 2.0\n}\n");
+        assert_eq!(vars_from_json(&json).unwrap(), vec![
+            ("rate".to_string(), 0.5), ("tiny".to_string(), 1e-300), ("x".to_string(), 2.0)]);
+        assert_eq!(vars_to_json(&Context::new()).unwrap(), "{}\n");
+    }
+
+    #[test]
+    fn escapes_names() {
+        let ctx = Context::new().with_var("a\"b\\c\n", 1.0);
+        let json = vars_to_json(&ctx).unwrap();
+        assert_eq!(vars_from_json(&json).unwrap(), vec![("a\"b\\c\n".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn rejects_non_finite_values() {
+        let ctx = Context::new().with_var("big", f64::INFINITY);
+        assert!(matches!(vars_to_json(&ctx), Err(SessionError::NotFinite(name, _)) if name == "big"));
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        assert!(matches!(vars_from_json("{\"x\": \"two\"}"), Err(SessionError::InvalidJson(_))));
+        assert!(matches!(vars_from_json("{\"x\": 1,}"), Err(SessionError::InvalidJson(_))));
+        assert!(matches!(vars_from_json("[1]"), Err(SessionError::InvalidJson(_))));
+    }
+
+    #[test]
+    fn loading_a_constant_sets_nothing() {
+        let path = env::temp_dir().join(format!("rcalc-session-{}-constant.json", process::id()));
+        fs::write(&path, "{\"a\": 1.0, \"pi\": 3.0, \"z\": 2.0}").unwrap();
+        let mut ctx = Context::new();
+        let result = load_vars(&mut ctx, path.to_str().unwrap());
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(result, Err(SessionError::Eval(EvalError::ConstantAssignment(name))) if name == "pi"));
+        assert_eq!(ctx.vars().count(), 0);
+    }
+}
@@ -0,0 +1,176 @@
+//! This module converts AST to and from Lisp style S-expressions, e.g. `1+2*3` and `(+ 1 (* 2 3))`.
+use std::iter::Peekable;
+use std::vec::IntoIter;
+
+use super::ast::Node;
+use super::parser::ParseErr;
+
+/// Renders the node as an S-expression. Negation is `(- x)`, a percent change `(%change x y)`
+/// and calls are `(name args...)`.
+pub fn to_sexpr(node: &Node) -> String {
+    use self::Node::*;
+    match node {
+        Number(n) => n.to_string(),
+        Variable(name) => name.clone(),
+        Add(left, right) => binary("+", left, right),
+        Subtract(left, right) => binary("-", left, right),
+        Multiply(left, right) => binary("*", left, right),
+        Divide(left, right) => binary("/", left, right),
+        Caret(left, right) => binary("^", left, right),
+        Equal(left, right) => binary("==", left, right),
+        NotEqual(left, right) => binary("!=", left, right),
+        PercentChange(left, right) => binary("%change", left, right),
+        Negative(expr) => format!("(- {})", to_sexpr(expr)),
+        Assign(name, expr) => format!("(= {} {})", name, to_sexpr(expr)),
+        Call(name, args) => {
+            let mut sexpr = format!("({}", name);
+            for arg in args {
+                sexpr.push(' ');
+                sexpr.push_str(&to_sexpr(arg));
+            }
+            sexpr.push(')');
+            sexpr
+        }
+    }
+}
+
+fn binary(oper: &str, left: &Node, right: &Node) -> String {
+    format!("({} {} {})", oper, to_sexpr(left), to_sexpr(right))
+}
+
+/// Parses an S-expression such as `(+ 1 (* 2 3))` back into AST
+pub fn from_sexpr(s: &str) -> Result<Node, ParseErr> {
+    let mut atoms = split_atoms(s).into_iter().peekable();
+    let node = parse_sexpr(&mut atoms)?;
+    match atoms.next() {
+        None => Ok(node),
+        Some(atom) => Err(ParseErr::UnableToParse(format!(
+            "Unexpected {} after end of S-expression", atom))),
+    }
+}
+
+/// Splits the input into parenthesis and whitespace separated atoms
+fn split_atoms(s: &str) -> Vec<String> {
+    s.replace('(', " ( ")
+        .replace(')', " ) ")
+        .split_whitespace()
+        .map(String::from)
+        .collect()
+}
+
+fn parse_sexpr(atoms: &mut Peekable<IntoIter<String>>) -> Result<Node, ParseErr> {
+    let atom = atoms.next()
+        .ok_or_else(|| ParseErr::UnableToParse("Unexpected end of S-expression".into()))?;
+    match atom.as_str() {
+        "(" => parse_list(atoms),
+        ")" => Err(ParseErr::UnableToParse("Unexpected )".into())),
+        _ => parse_atom(&atom),
+    }
+}
+
+/// Parses the contents of a list after its opening parenthesis
+fn parse_list(atoms: &mut Peekable<IntoIter<String>>) -> Result<Node, ParseErr> {
+    let head = match atoms.next() {
+        Some(head) if head != "(" && head != ")" => head,
+        _ => return Err(ParseErr::UnableToParse(
+            "Expected operator or function name after (".into())),
+    };
+
+    let mut args = Vec::new();
+    loop {
+        match atoms.peek().map(String::as_str) {
+            Some(")") => {
+                atoms.next();
+                break;
+            }
+            Some(_) => args.push(parse_sexpr(atoms)?),
+            None => return Err(ParseErr::UnableToParse("Missing )".into())),
+        }
+    }
+
+    let arg_count = args.len();
+    let mut args = args.into_iter();
+    let mut next_arg = || Box::new(args.next().unwrap());
+    match (head.as_str(), arg_count) {
+        ("+", 2) => Ok(Node::Add(next_arg(), next_arg())),
+        ("-", 2) => Ok(Node::Subtract(next_arg(), next_arg())),
+        ("-", 1) => Ok(Node::Negative(next_arg())),
+        ("*", 2) => Ok(Node::Multiply(next_arg(), next_arg())),
+        ("/", 2) => Ok(Node::Divide(next_arg(), next_arg())),
+        ("^", 2) => Ok(Node::Caret(next_arg(), next_arg())),
+        ("==", 2) => Ok(Node::Equal(next_arg(), next_arg())),
+        ("!=", 2) => Ok(Node::NotEqual(next_arg(), next_arg())),
+        ("%change", 2) => Ok(Node::PercentChange(next_arg(), next_arg())),
+        ("=", 2) => match *next_arg() {
+            Node::Variable(name) => Ok(Node::Assign(name, next_arg())),
+            _ => Err(ParseErr::UnableToParse(
+                "Left side of = must be a variable".into())),
+        },
+        ("+" | "-" | "*" | "/" | "^" | "=" | "==" | "!=" | "%change", _) => Err(ParseErr::InvalidOperator(format!(
+            "Operator {} can not take {} operand(s)", head, arg_count))),
+        (name, _) if is_identifier(name) => Ok(Node::Call(name.to_string(), args.collect())),
+        (name, _) => Err(ParseErr::InvalidOperator(format!(
+            "Invalid operator {}", name))),
+    }
+}
+
+pub(crate) fn parse_atom(atom: &str) -> Result<Node, ParseErr> {
+    // Identifiers such as `inf` or `nan` would otherwise parse as floats
+    let numeric = atom.trim_start_matches(['+', '-'])
+        .starts_with(|c: char| c.is_ascii_digit() || c == '.');
+    if let (true, Ok(number)) = (numeric, atom.parse::<f64>()) {
+        Ok(Node::Number(number))
+    } else if is_identifier(atom) {
+        Ok(Node::Variable(atom.to_string()))
+    } else {
+        Err(ParseErr::UnableToParse(format!("Invalid atom {}", atom)))
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::Parser;
+
+    fn parse(expr: &str) -> Node {
+        Parser::new(expr).unwrap().parse().unwrap()
+    }
+
+    #[test]
+    fn renders_s_expressions() {
+        assert_eq!(to_sexpr(&parse("1+2*3")), "(+ 1 (* 2 3))");
+        assert_eq!(to_sexpr(&parse("(1 - x^2) / max(-a, 1.5)")), "(/ (- 1 (^ x 2)) (max (- a) 1.5))");
+        assert_eq!(to_sexpr(&parse("y = 2")), "(= y 2)");
+    }
+
+    #[test]
+    fn round_trips_through_s_expressions() {
+        for expr in ["1+2*3", "(1 - x^2) / max(-a, 1.5)", "y = sqrt(2) == 1", "f()"] {
+            let tree = parse(expr);
+            assert_eq!(from_sexpr(&to_sexpr(&tree)).unwrap(), tree, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn parses_nested_s_expressions() {
+        assert_eq!(from_sexpr(" (+ 1 (* 2  3)) ").unwrap(), parse("1+2*3"));
+        assert_eq!(from_sexpr("-2.5").unwrap(), Node::Number(-2.5));
+    }
+
+    #[test]
+    fn rejects_bad_s_expressions() {
+        let error = |s: &str| from_sexpr(s).unwrap_err().to_string();
+        assert_eq!(error("(+ 1 2"), "Error in evaluating Missing )");
+        assert_eq!(error("(+ 1 2))"), "Error in evaluating Unexpected ) after end of S-expression");
+        assert_eq!(error("()"), "Error in evaluating Expected operator or function name after (");
+        assert_eq!(error("(+ 1)"), "Error in evaluating Operator + can not take 1 operand(s)");
+        assert_eq!(error("(= 1 2)"), "Error in evaluating Left side of = must be a variable");
+        assert_eq!(error(""), "Error in evaluating Unexpected end of S-expression");
+    }
+}
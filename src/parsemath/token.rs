@@ -1,6 +1,7 @@
-/// This module contains Token structure
+//! This module contains Token structure
 
 /// Defines list of valid tokens that can be constructed from arithmetic expression by Tokenizer
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Debug, Clone)]
 pub enum Token {
     Add,
@@ -10,7 +11,26 @@ pub enum Token {
     Caret,
     LeftParen,
     RightParen,
+    LeftBracket,
+    RightBracket,
+    Comma,
+    Semicolon,
+    Assign,
+    Equal,
+    NotEqual,
+    /// Postfix keyword `squared`, raising to the power of 2
+    Squared,
+    /// Postfix keyword `cubed`, raising to the power of 3
+    Cubed,
+    /// Keyword `from` of a percent change `from X to Y`
+    From,
+    /// Keyword `to` of a percent change `from X to Y`
+    To,
+    Ident(String),
     Num(f64),
+    /// Number written with a decimal point or an exponent, such as `2.0`,
+    /// read only by a tokenizer with `float_literals` on
+    Float(f64),
     EOF,
 }
 
@@ -19,8 +39,11 @@ pub enum Token {
 #[derive(Debug, PartialEq, PartialOrd)]
 pub enum OperPrec {
     DefaultZero,
+    Compare,
     AddSub,
     MulDiv,
+    /// Implicit multiplication such as `2x`, binding tighter than `/` so `10km / 2h` is `10km / (2h)`
+    ImplicitMul,
     Power,
     Negative,
 }
@@ -30,9 +53,10 @@ impl Token {
         use self::OperPrec::*;
         use self::Token::*;
         match *self {
+            Equal | NotEqual => Compare,
             Add | Substract => AddSub,
             Multiply | Divide => MulDiv,
-            Caret => Power,
+            Caret | Squared | Cubed => Power,
 
             _ => DefaultZero
         }
@@ -1,52 +1,379 @@
-/// This module reads characters in arithmetic expression and converts them to tokens.
-use std::str::Chars;
+//! This module reads characters in arithmetic expression and converts them to tokens.
+use std::str::CharIndices;
 use std::iter::Peekable;
+use std::fmt;
 
 use super::token::Token;
 
+#[derive(Clone)]
 pub struct Tokenizer<'a> {
-    expr: Peekable<Chars<'a>>
+    expr: Peekable<CharIndices<'a>>,
+    len: usize,
+    token_start: usize,
+    word_operators: bool,
+    percent_keywords: bool,
+    metavariables: bool,
+    float_literals: bool,
 }
 
 impl<'a> Tokenizer<'a> {
     pub fn new(new_expr: &'a str) -> Self {
         Tokenizer { 
-            expr: new_expr.chars().peekable() 
+            expr: new_expr.char_indices().peekable(),
+            len: new_expr.len(),
+            token_start: 0,
+            word_operators: false,
+            percent_keywords: false,
+            metavariables: false,
+            float_literals: false,
         }
     }
+
+    /// Reads `plus`, `minus`, `times` and `over` as `+`, `-`, `*` and `/`.
+    /// Off by default, as these are otherwise valid variable names.
+    pub fn word_operators(mut self, enabled: bool) -> Self {
+        self.word_operators = enabled;
+        self
+    }
+
+    /// Reads `from` and `to` as keywords of the percent change `from X to Y`.
+    /// Off by default, as these are otherwise valid variable names.
+    pub fn percent_keywords(mut self, enabled: bool) -> Self {
+        self.percent_keywords = enabled;
+        self
+    }
+
+    /// Reads names starting with `?`, such as `?a`, as used by metavariables of rewrite rules.
+    /// Off by default, so `?` is an invalid character in expressions.
+    pub fn metavariables(mut self, enabled: bool) -> Self {
+        self.metavariables = enabled;
+        self
+    }
+
+    /// Reads numbers written with a decimal point or an exponent, such as `2.0` or `1e3`,
+    /// as `Token::Float`, so mixed integer evaluation can keep them floats
+    pub fn float_literals(mut self, enabled: bool) -> Self {
+        self.float_literals = enabled;
+        self
+    }
+
+    /// Byte offset in the expression where the most recently returned token starts
+    pub fn position(&self) -> usize {
+        self.token_start
+    }
+
+    /// Byte offset just past the most recently returned token
+    pub(crate) fn end_position(&mut self) -> usize {
+        self.offset()
+    }
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Byte offset of the next unread character
+    fn offset(&mut self) -> usize {
+        self.expr.peek().map_or(self.len, |&(i, _)| i)
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        self.expr.next().map(|(_, c)| c)
+    }
+
+    fn peek_char(&mut self) -> Option<char> {
+        self.expr.peek().map(|&(_, c)| c)
+    }
+
+    fn next_char_if(&mut self, pred: impl Fn(char) -> bool) -> Option<char> {
+        self.expr.next_if(|&(_, c)| pred(c)).map(|(_, c)| c)
+    }
+
+    /// Whether the next character after any whitespace is `(`, as in a function call
+    fn followed_by_paren(&self) -> bool {
+        let mut lookahead = self.expr.clone();
+        while lookahead.next_if(|&(_, c)| c.is_whitespace()).is_some() {}
+        lookahead.peek().is_some_and(|&(_, c)| c == '(')
+    }
+
+    /// Appends a scientific notation exponent such as `e-5` to the number, if present.
+    /// Returns false for an incomplete exponent such as `1e` or `1e+`. An `e` starting a name,
+    /// as in `2exp(1)`, is not an exponent.
+    fn read_exponent(&mut self, number: &mut String) -> bool {
+        if !matches!(self.peek_char(), Some('e') | Some('E')) {
+            return true;
+        }
+        let mut lookahead = self.expr.clone();
+        lookahead.next();
+        let signed = lookahead.next_if(|&(_, c)| c == '+' || c == '-').is_some();
+        match lookahead.peek() {
+            Some((_, c)) if c.is_ascii_digit() => {}
+            Some((_, c)) if !signed && (c.is_alphanumeric() || *c == '_') => return true,
+            _ => return false,
+        }
+
+        number.extend(self.next_char());
+        number.extend(self.next_char_if(|c| c == '+' || c == '-'));
+        while let Some(digit) = self.next_char_if(|c| c.is_ascii_digit()) {
+            number.push(digit);
+        }
+        true
+    }
 }
 
 impl<'a> Iterator for Tokenizer<'a> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Token> {
-        let next_char = self.expr.next();
+        let token = self.read_token();
+        #[cfg(feature = "tracing")]
+        tracing::trace!(token = ?token, position = self.token_start, "token");
+        token
+    }
+}
+
+impl<'a> Tokenizer<'a> {
+    fn read_token(&mut self) -> Option<Token> {
+        while self.next_char_if(char::is_whitespace).is_some() {}
+        self.token_start = self.offset();
+        let next_char = self.next_char();
 
         match next_char {
             Some('0'..='9') => {
                 let mut number = next_char?.to_string();
 
-                while let Some(next_char) = self.expr.peek() {
-                    if next_char.is_numeric() || next_char == &'.' {
-                        number.push(self.expr.next()?);
-                    } else if next_char == &'(' {
+                while let Some(next_char) = self.peek_char() {
+                    if next_char.is_numeric() || next_char == '.' {
+                        number.push(self.next_char()?);
+                    } else if next_char == '(' {
                         return None;
                     } else {
                         break;
                     }
                 }
+                if !self.read_exponent(&mut number) {
+                    return None;
+                }
 
-                Some(Token::Num(number.parse::<f64>().unwrap()))
+                let real = number.contains(['.', 'e', 'E']);
+                let number = number.parse::<f64>().ok()?;
+                if self.float_literals && real {
+                    Some(Token::Float(number))
+                } else {
+                    Some(Token::Num(number))
+                }
             },
             Some('+') => Some(Token::Add),
             Some('-') => Some(Token::Substract),
-            Some('*') => Some(Token::Multiply),
+            // Dot operators, as in 2·x or 2⋅x, are explicit multiplications
+            Some('*' | '·' | '⋅') => Some(Token::Multiply),
             Some('/') => Some(Token::Divide),
             Some('^') => Some(Token::Caret),
             Some('(') => Some(Token::LeftParen),
             Some(')') => Some(Token::RightParen),
+            Some('[') => Some(Token::LeftBracket),
+            Some(']') => Some(Token::RightBracket),
+            Some(',') => Some(Token::Comma),
+            Some(';') => Some(Token::Semicolon),
+            Some('=') if self.next_char_if(|c| c == '=').is_some() => Some(Token::Equal),
+            Some('!') if self.next_char_if(|c| c == '=').is_some() => Some(Token::NotEqual),
+            Some('=') => Some(Token::Assign),
+            Some(c) if c.is_alphabetic() || c == '_' || (c == '?' && self.metavariables) => {
+                let mut name = c.to_string();
+
+                while let Some(next_char) = self.next_char_if(|c| c.is_alphanumeric() || c == '_') {
+                    name.push(next_char);
+                }
+
+                match name.as_str() {
+                    "squared" => Some(Token::Squared),
+                    "cubed" => Some(Token::Cubed),
+                    // to(..) stays a function call, like to(1km, m) with units, which the parser
+                    // still takes for the keyword after from, as in from 1 to (2 + 3)
+                    "from" if self.percent_keywords => Some(Token::From),
+                    "to" if self.percent_keywords && !self.followed_by_paren() => Some(Token::To),
+                    "plus" if self.word_operators => Some(Token::Add),
+                    "minus" if self.word_operators => Some(Token::Substract),
+                    "times" if self.word_operators => Some(Token::Multiply),
+                    "over" if self.word_operators => Some(Token::Divide),
+                    _ => Some(Token::Ident(name)),
+                }
+            },
             None => Some(Token::EOF),
             Some(_) => None,
         }
     }
-}
\ No newline at end of file
+}
+
+/// Character the tokenizer could not read, with its byte offset in the expression
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexErr {
+    pub position: usize,
+    pub ch: char,
+}
+
+impl fmt::Display for LexErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid character '{}' at position {}", self.ch, self.position)
+    }
+}
+
+/// Tokenizes the whole expression, skipping what can not be read instead of stopping.
+/// Returns the valid tokens, without the final EOF, and an error for each skipped token.
+pub fn tokenize_lossy(expr: &str) -> (Vec<Token>, Vec<LexErr>) {
+    let mut tokenizer = Tokenizer::new(expr);
+    let mut tokens = Vec::new();
+    let mut errors = Vec::new();
+    loop {
+        match tokenizer.next() {
+            Some(Token::EOF) => return (tokens, errors),
+            Some(token) => tokens.push(token),
+            None => {
+                let position = tokenizer.position();
+                errors.extend(expr[position..].chars().next().map(|ch| LexErr { position, ch }));
+            }
+        }
+    }
+}
+
+/// Quickly checks that `()`, `[]`, `{}` and `|` bars are balanced, without full parsing.
+/// A bar closes the innermost open bar if that is the innermost bracket, otherwise it opens one,
+/// so `|a| + |b|` is balanced. Text after `#` up to the end of the line is a comment and ignored.
+pub fn is_balanced(expr: &str) -> bool {
+    let mut open = Vec::new();
+    for line in expr.lines() {
+        let code = line.split('#').next().unwrap_or("");
+        for c in code.chars() {
+            match c {
+                '(' | '[' | '{' => open.push(c),
+                '|' if open.last() == Some(&'|') => {
+                    open.pop();
+                }
+                '|' => open.push(c),
+                ')' | ']' | '}' => {
+                    let expected = match c {
+                        ')' => '(',
+                        ']' => '[',
+                        _ => '{',
+                    };
+                    if open.pop() != Some(expected) {
+                        return false;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    open.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads tokens up to and including EOF
+    fn tokens(expr: &str) -> Vec<Token> {
+        let mut tokenizer = Tokenizer::new(expr);
+        let mut tokens = Vec::new();
+        loop {
+            let token = tokenizer.next().expect("valid token");
+            let end = token == Token::EOF;
+            tokens.push(token);
+            if end {
+                return tokens;
+            }
+        }
+    }
+
+    #[test]
+    fn tokenizes_function_call_with_comma() {
+        assert_eq!(tokens("max(1, 2)"), vec![
+            Token::Ident("max".into()),
+            Token::LeftParen,
+            Token::Num(1.0),
+            Token::Comma,
+            Token::Num(2.0),
+            Token::RightParen,
+            Token::EOF,
+        ]);
+    }
+
+    #[test]
+    fn reads_scientific_notation() {
+        assert_eq!(tokens("1e-5"), vec![Token::Num(1e-5), Token::EOF]);
+        assert_eq!(tokens("2E+3"), vec![Token::Num(2000.0), Token::EOF]);
+        assert_eq!(tokens("1.5e3 * 2"), vec![Token::Num(1500.0), Token::Multiply, Token::Num(2.0), Token::EOF]);
+    }
+
+    #[test]
+    fn rejects_incomplete_exponent() {
+        assert_eq!(Tokenizer::new("1e").next(), None);
+        assert_eq!(Tokenizer::new("1e+").next(), None);
+        assert_eq!(Tokenizer::new("2e-x").next(), None);
+    }
+
+    #[test]
+    fn balanced_brackets() {
+        assert!(is_balanced("(1 + [2, {3}]) * |a| + |b|"));
+        assert!(is_balanced(""));
+        assert!(is_balanced("(1 + 2) # ) unbalanced in a comment"));
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        assert!(!is_balanced("(1 + 2"));
+        assert!(!is_balanced("1 + 2)"));
+        assert!(!is_balanced("|a"));
+        assert!(!is_balanced("(1 # )"));
+    }
+
+    #[test]
+    fn mismatched_bracket_kinds() {
+        assert!(!is_balanced("(1 + 2]"));
+        assert!(!is_balanced("[(1 + 2])"));
+        assert!(!is_balanced("(|a)|"));
+    }
+
+    #[test]
+    fn lossy_tokenizing_skips_invalid_characters() {
+        let (tokens, errors) = tokenize_lossy("3 @ 4 $ 5");
+        assert_eq!(tokens, vec![Token::Num(3.0), Token::Num(4.0), Token::Num(5.0)]);
+        assert_eq!(errors, vec![LexErr { position: 2, ch: '@' }, LexErr { position: 6, ch: '$' }]);
+        assert_eq!(errors[0].to_string(), "Invalid character '@' at position 2");
+    }
+
+    #[test]
+    fn question_marks_start_names_only_for_metavariables() {
+        assert_eq!(Tokenizer::new("?x + 1").next(), None);
+        let (_, errors) = tokenize_lossy("?x + 1");
+        assert_eq!(errors, vec![LexErr { position: 0, ch: '?' }]);
+
+        let mut tokenizer = Tokenizer::new("?x + 1").metavariables(true);
+        assert_eq!(tokenizer.next(), Some(Token::Ident("?x".into())));
+        assert_eq!(tokenizer.next(), Some(Token::Add));
+    }
+
+    #[test]
+    fn real_literals_are_floats_only_when_enabled() {
+        assert_eq!(Tokenizer::new("2.0").next(), Some(Token::Num(2.0)));
+        let mut tokenizer = Tokenizer::new("2.0 + 2 + 1e3").float_literals(true);
+        assert_eq!(tokenizer.next(), Some(Token::Float(2.0)));
+        assert_eq!(tokenizer.next(), Some(Token::Add));
+        assert_eq!(tokenizer.next(), Some(Token::Num(2.0)));
+        assert_eq!(tokenizer.next(), Some(Token::Add));
+        assert_eq!(tokenizer.next(), Some(Token::Float(1000.0)));
+    }
+
+    #[test]
+    fn percent_keywords_are_names_unless_enabled() {
+        assert_eq!(tokens("from + to"), vec![
+            Token::Ident("from".into()),
+            Token::Add,
+            Token::Ident("to".into()),
+            Token::EOF,
+        ]);
+        let mut tokenizer = Tokenizer::new("from(1) to(2)").percent_keywords(true);
+        assert_eq!(tokenizer.next(), Some(Token::From));
+        assert_eq!(tokenizer.next(), Some(Token::LeftParen));
+        assert_eq!(tokenizer.next(), Some(Token::Num(1.0)));
+        assert_eq!(tokenizer.next(), Some(Token::RightParen));
+        assert_eq!(tokenizer.next(), Some(Token::Ident("to".into())));
+    }
+}
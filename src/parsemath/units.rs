@@ -0,0 +1,371 @@
+//! This module evaluates AST over quantities with units, available with the `units` feature.
+//! Names of known units such as `km` or `h` evaluate to one of that unit, so `5km` is a quantity.
+//! Addition and subtraction convert to the unit of the left operand, so `1km + 500m` is `1.5 km`,
+//! while multiplication and division combine units.
+use std::fmt;
+
+use super::ast::{self, EvalError, Node};
+use super::context::{Arity, Context};
+use super::format::format_result;
+
+/// Exponents of the base dimensions
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Dimension {
+    pub length: i32,
+    pub time: i32,
+    pub mass: i32,
+}
+
+impl Dimension {
+    pub const LENGTH: Dimension = Dimension { length: 1, time: 0, mass: 0 };
+    pub const TIME: Dimension = Dimension { length: 0, time: 1, mass: 0 };
+    pub const MASS: Dimension = Dimension { length: 0, time: 0, mass: 1 };
+
+    pub fn is_dimensionless(&self) -> bool {
+        *self == Dimension::default()
+    }
+
+    fn combine(&self, other: &Dimension, sign: i32) -> Dimension {
+        Dimension {
+            length: self.length + sign * other.length,
+            time: self.time + sign * other.time,
+            mass: self.mass + sign * other.mass,
+        }
+    }
+
+    fn scale(&self, exponent: i32) -> Dimension {
+        Dimension {
+            length: self.length * exponent,
+            time: self.time * exponent,
+            mass: self.mass * exponent,
+        }
+    }
+}
+
+/// Unit of measure: its dimension and the factor converting it to meters, seconds and kilograms
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unit {
+    name: String,
+    dimension: Dimension,
+    factor: f64,
+}
+
+/// Units that can be written after numbers
+const REGISTRY: &[(&str, Dimension, f64)] = &[
+    ("mm", Dimension::LENGTH, 0.001),
+    ("cm", Dimension::LENGTH, 0.01),
+    ("m", Dimension::LENGTH, 1.0),
+    ("km", Dimension::LENGTH, 1000.0),
+    ("ms", Dimension::TIME, 0.001),
+    ("s", Dimension::TIME, 1.0),
+    ("min", Dimension::TIME, 60.0),
+    ("h", Dimension::TIME, 3600.0),
+    ("mg", Dimension::MASS, 0.000001),
+    ("g", Dimension::MASS, 0.001),
+    ("kg", Dimension::MASS, 1.0),
+];
+
+impl Unit {
+    /// Unit of plain numbers
+    pub fn none() -> Self {
+        Unit { name: String::new(), dimension: Dimension::default(), factor: 1.0 }
+    }
+
+    /// Finds a unit of the registry by name
+    pub fn lookup(name: &str) -> Option<Self> {
+        REGISTRY.iter()
+            .find(|(unit, _, _)| *unit == name)
+            .map(|&(name, dimension, factor)| Unit { name: name.to_string(), dimension, factor })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dimension(&self) -> Dimension {
+        self.dimension
+    }
+
+    pub fn factor(&self) -> f64 {
+        self.factor
+    }
+
+    pub fn is_dimensionless(&self) -> bool {
+        self.dimension.is_dimensionless()
+    }
+
+    fn mul(&self, other: &Unit) -> Unit {
+        if self.name == other.name {
+            return self.powi(2);
+        }
+        Unit {
+            name: format!("{}*{}", self.name, other.name),
+            dimension: self.dimension.combine(&other.dimension, 1),
+            factor: self.factor * other.factor,
+        }
+    }
+
+    fn div(&self, other: &Unit) -> Unit {
+        Unit {
+            name: format!("{}/{}", self.name, other.name),
+            dimension: self.dimension.combine(&other.dimension, -1),
+            factor: self.factor / other.factor,
+        }
+    }
+
+    fn powi(&self, exponent: i32) -> Unit {
+        Unit {
+            name: format!("{}^{}", self.name, exponent),
+            dimension: self.dimension.scale(exponent),
+            factor: self.factor.powi(exponent),
+        }
+    }
+}
+
+/// Number with a unit
+#[derive(Clone, Debug, PartialEq)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: Unit,
+}
+
+impl Quantity {
+    pub fn new(value: f64, unit: Unit) -> Self {
+        Quantity { value, unit }
+    }
+
+    pub fn number(value: f64) -> Self {
+        Quantity { value, unit: Unit::none() }
+    }
+
+    /// Converts to another unit of the same dimension
+    pub fn convert(&self, unit: &Unit) -> Result<Quantity, EvalError> {
+        if self.unit.dimension != unit.dimension {
+            return Err(incompatible(&self.unit, unit));
+        }
+        Ok(Quantity::new(self.value * self.unit.factor / unit.factor, unit.clone()))
+    }
+
+    /// Drops a unit whose dimensions cancelled out, e.g. `km/m`, scaling the value accordingly
+    fn simplify(self) -> Quantity {
+        if self.unit.is_dimensionless() && !self.unit.name.is_empty() {
+            Quantity::number(self.value * self.unit.factor)
+        } else {
+            self
+        }
+    }
+
+    fn add(&self, other: &Quantity) -> Result<Quantity, EvalError> {
+        let other = other.convert(&self.unit)?;
+        Ok(Quantity::new(self.value + other.value, self.unit.clone()))
+    }
+
+    fn sub(&self, other: &Quantity) -> Result<Quantity, EvalError> {
+        let other = other.convert(&self.unit)?;
+        Ok(Quantity::new(self.value - other.value, self.unit.clone()))
+    }
+
+    fn mul(&self, other: &Quantity) -> Quantity {
+        let unit = match (self.unit.name(), other.unit.name()) {
+            (_, "") => self.unit.clone(),
+            ("", _) => other.unit.clone(),
+            _ => self.unit.mul(&other.unit),
+        };
+        Quantity::new(self.value * other.value, unit).simplify()
+    }
+
+    fn div(&self, other: &Quantity) -> Quantity {
+        let unit = match (self.unit.name(), other.unit.name()) {
+            (_, "") => self.unit.clone(),
+            _ => self.unit.div(&other.unit),
+        };
+        Quantity::new(self.value / other.value, unit).simplify()
+    }
+
+    fn pow(&self, exponent: &Quantity) -> Result<Quantity, EvalError> {
+        let exponent = exponent.dimensionless()?;
+        let value = ast::power(self.value, exponent)?;
+        if self.unit.name().is_empty() {
+            return Ok(Quantity::number(value));
+        }
+        if exponent.fract() != 0.0 {
+            return Err(EvalError::InvalidArgument(format!(
+                "{} can only be raised to integer powers", self.unit.name())));
+        }
+        Ok(Quantity::new(value, self.unit.powi(exponent as i32)).simplify())
+    }
+
+    /// Returns the bare number, failing if the quantity still has a unit
+    pub fn dimensionless(&self) -> Result<f64, EvalError> {
+        if self.unit.name().is_empty() {
+            Ok(self.value)
+        } else {
+            Err(incompatible(&self.unit, &Unit::none()))
+        }
+    }
+}
+
+/// Displays the value followed by the unit, e.g. `1.5 km`
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.unit.name().is_empty() {
+            write!(f, "{}", format_result(self.value))
+        } else {
+            write!(f, "{} {}", format_result(self.value), self.unit.name())
+        }
+    }
+}
+
+fn incompatible(from: &Unit, to: &Unit) -> EvalError {
+    let describe = |unit: &Unit| match unit.name() {
+        "" => "no unit".to_string(),
+        name => name.to_string(),
+    };
+    EvalError::IncompatibleUnits(describe(from), describe(to))
+}
+
+/// Evaluates the AST over quantities. Variables and constants are plain numbers and shadow units
+/// of the same name. Functions only accept plain numbers.
+pub fn eval_units(expr: &Node, ctx: &mut Context) -> Result<Quantity, EvalError> {
+    use self::Node::*;
+    match expr {
+        Number(n) => Ok(Quantity::number(*n * ctx.input_scale_factor())),
+        Variable(name) => match (ctx.lookup(name), Unit::lookup(name)) {
+            (Some(value), _) => Ok(Quantity::number(value)),
+            (None, Some(unit)) => Ok(Quantity::new(1.0, unit)),
+            (None, None) => Err(EvalError::UnboundVariable(name.clone())),
+        },
+        Add(expr1, expr2) => {
+            let left = eval_units(expr1, ctx)?;
+            left.add(&eval_units(expr2, ctx)?)
+        }
+        Subtract(expr1, expr2) => {
+            let left = eval_units(expr1, ctx)?;
+            left.sub(&eval_units(expr2, ctx)?)
+        }
+        Multiply(expr1, expr2) => {
+            let left = eval_units(expr1, ctx)?;
+            Ok(left.mul(&eval_units(expr2, ctx)?))
+        }
+        Divide(expr1, expr2) => {
+            let left = eval_units(expr1, ctx)?;
+            Ok(left.div(&eval_units(expr2, ctx)?))
+        }
+        Caret(expr1, expr2) => {
+            let base = eval_units(expr1, ctx)?;
+            base.pow(&ctx.unscaled(|ctx| eval_units(expr2, ctx))?)
+        }
+        Negative(expr1) => {
+            let quantity = eval_units(expr1, ctx)?;
+            Ok(Quantity::new(-quantity.value, quantity.unit))
+        }
+        Call(name, args) if name == "to" || name == "dimensionless" => {
+            let args = args.iter()
+                .map(|arg| eval_units(arg, ctx))
+                .collect::<Result<Vec<Quantity>, EvalError>>()?;
+            call_unit_function(name, &args)
+        }
+        Call(name, args) => {
+            let func = ctx.get_func(name)
+                .cloned()
+                .ok_or_else(|| EvalError::UnknownFunction(name.clone()))?;
+            let args = args.iter()
+                .map(|arg| eval_units(arg, ctx)?.dimensionless())
+                .collect::<Result<Vec<f64>, EvalError>>()?;
+            Ok(Quantity::number(ctx.call_in_scope(name, &func, &args)?))
+        }
+        Assign(name, expr1) => {
+            let quantity = eval_units(expr1, ctx)?;
+            ctx.set_var(name, quantity.dimensionless()?)?;
+            Ok(quantity)
+        }
+        Equal(expr1, expr2) | NotEqual(expr1, expr2) => {
+            let left = eval_units(expr1, ctx)?;
+            let right = eval_units(expr2, ctx)?.convert(&left.unit)?;
+            let equal = ctx.approx_eq(left.value, right.value) != matches!(expr, NotEqual(..));
+            Ok(Quantity::number(if equal { 1.0 } else { 0.0 }))
+        }
+        // The change between quantities of the same dimension, as a plain number
+        PercentChange(expr1, expr2) => {
+            let from = eval_units(expr1, ctx)?;
+            let to = eval_units(expr2, ctx)?.convert(&from.unit)?;
+            Ok(Quantity::number(ast::percent_change(from.value, to.value)?))
+        }
+    }
+}
+
+/// Functions taking quantities and returning plain numbers:
+/// `to(q, unit)` converts q to the unit, e.g. `to(1km, m)` is `1000`,
+/// and `dimensionless(q)` fails unless the units of q cancelled out.
+fn call_unit_function(name: &str, args: &[Quantity]) -> Result<Quantity, EvalError> {
+    let argument_count = |expected: usize| EvalError::ArgumentCount {
+        function: name.to_string(),
+        expected: Arity::Exact(expected),
+        got: args.len(),
+    };
+    match (name, args) {
+        ("to", [quantity, target]) => {
+            if target.value != 1.0 || target.unit.name().is_empty() {
+                return Err(EvalError::InvalidArgument(format!(
+                    "to expects a unit as second argument, got {}", target)));
+            }
+            Ok(Quantity::number(quantity.convert(&target.unit)?.value))
+        }
+        ("to", _) => Err(argument_count(2)),
+        ("dimensionless", [quantity]) => Ok(Quantity::number(quantity.dimensionless()?)),
+        _ => Err(argument_count(1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::parser::Parser;
+
+    fn eval(expr: &str) -> Result<Quantity, EvalError> {
+        eval_units(&Parser::new(expr).unwrap().parse().unwrap(), &mut Context::new())
+    }
+
+    #[test]
+    fn converts_compatible_units() {
+        assert_eq!(eval("1km + 500m").unwrap().to_string(), "1.5 km");
+        assert_eq!(eval("1km + 500m == 1.5km").unwrap(), Quantity::number(1.0));
+        assert_eq!(eval("2h - 30min").unwrap().to_string(), "1.5 h");
+    }
+
+    #[test]
+    fn combines_units_in_products() {
+        assert_eq!(eval("10km / 2h").unwrap().to_string(), "5 km/h");
+        assert_eq!(eval("3m * 2m").unwrap().to_string(), "6 m^2");
+        assert_eq!(eval("1km / 1m").unwrap(), Quantity::number(1000.0));
+    }
+
+    #[test]
+    fn rejects_incompatible_units() {
+        assert_eq!(eval("1km + 1h"), Err(EvalError::IncompatibleUnits("h".into(), "km".into())));
+        assert_eq!(eval("1kg + 1"), Err(EvalError::IncompatibleUnits("no unit".into(), "kg".into())));
+    }
+
+    #[test]
+    fn to_converts_into_plain_numbers() {
+        assert_eq!(eval("to(1km, m)"), Ok(Quantity::number(1000.0)));
+        assert_eq!(eval("to(90min, h)"), Ok(Quantity::number(1.5)));
+        assert_eq!(eval("to(1km, h)"), Err(EvalError::IncompatibleUnits("km".into(), "h".into())));
+        assert!(matches!(eval("to(1km, 2m)"), Err(EvalError::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn percent_changes_convert_to_the_starting_unit() {
+        use super::super::tokenizer::Tokenizer;
+        let eval = |expr: &str| eval_units(&Parser::from_tokenizer(Tokenizer::new(expr).percent_keywords(true))
+            .unwrap().parse().unwrap(), &mut Context::new());
+        assert_eq!(eval("from 1km to 1500m"), Ok(Quantity::number(50.0)));
+        assert_eq!(eval("from 1km to 1h"), Err(EvalError::IncompatibleUnits("h".into(), "km".into())));
+    }
+
+    #[test]
+    fn dimensionless_strips_cancelled_units() {
+        assert_eq!(eval("dimensionless(3km / 1m)"), Ok(Quantity::number(3000.0)));
+        assert_eq!(eval("dimensionless(5km)"), Err(EvalError::IncompatibleUnits("km".into(), "no unit".into())));
+    }
+}
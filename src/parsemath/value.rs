@@ -0,0 +1,326 @@
+//! This module evaluates AST keeping integers apart from floats, so results display the way
+//! they arose: `2+2` shows `4` while `1/2` shows `0.5` and `2.5*2` shows `5.0`.
+//! Integer literals, sums, differences, products and non negative integer powers stay integers
+//! until they overflow i64, where they continue as floats, or fail with checked integers.
+//! Division, negative powers and functions give floats, except `abs`, `sign`, `floor` and `ceil`
+//! which keep integers, and `round` and `iround` which give integers.
+//! Expressions parsed with `parse_value` keep literals such as `2.0` floats, so `2.0 + 2` shows `4.0`.
+use std::fmt;
+
+use super::ast::{self, EvalError, Node};
+use super::context::Context;
+use super::format::format_result;
+use super::parser::{ParseErr, Parser};
+use super::tokenizer::Tokenizer;
+
+/// Result of mixed integer and float evaluation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl Value {
+    /// Integral floats within the exact range of f64 become integers, e.g. variables set to `3`
+    pub fn from_f64(value: f64) -> Self {
+        if value.fract() == 0.0 && value.abs() < 9_007_199_254_740_992.0 {
+            Value::Int(value as i64)
+        } else {
+            Value::Float(value)
+        }
+    }
+
+    /// Converts to a float; `true` and `false` are 1 and 0
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Value::Int(n) => *n as f64,
+            Value::Float(f) => *f,
+            Value::Bool(b) => if *b { 1.0 } else { 0.0 },
+        }
+    }
+
+    /// Returns the number, failing for booleans
+    pub fn number(&self) -> Result<f64, EvalError> {
+        match self {
+            Value::Bool(b) => Err(EvalError::TypeError(format!("expected a number, got {}", b))),
+            _ => Ok(self.to_f64()),
+        }
+    }
+
+    /// Absolute value; `abs` of the smallest i64 continues as a float
+    pub fn abs(&self) -> Result<Value, EvalError> {
+        match self {
+            Value::Int(n) => Ok(n.checked_abs().map_or(Value::Float((*n as f64).abs()), Value::Int)),
+            Value::Float(f) => Ok(Value::Float(f.abs())),
+            Value::Bool(b) => Err(not_a_number("abs", *b)),
+        }
+    }
+
+    /// -1, 0 or 1 for negative, zero and positive values, of the same kind as the value
+    pub fn sign(&self) -> Result<Value, EvalError> {
+        match self {
+            Value::Int(n) => Ok(Value::Int(n.signum())),
+            Value::Float(f) if *f == 0.0 => Ok(Value::Float(0.0)),
+            Value::Float(f) => Ok(Value::Float(f.signum())),
+            Value::Bool(b) => Err(not_a_number("sign", *b)),
+        }
+    }
+
+    pub fn floor(&self) -> Result<Value, EvalError> {
+        match self {
+            Value::Int(n) => Ok(Value::Int(*n)),
+            Value::Float(f) => Ok(Value::Float(f.floor())),
+            Value::Bool(b) => Err(not_a_number("floor", *b)),
+        }
+    }
+
+    pub fn ceil(&self) -> Result<Value, EvalError> {
+        match self {
+            Value::Int(n) => Ok(Value::Int(*n)),
+            Value::Float(f) => Ok(Value::Float(f.ceil())),
+            Value::Bool(b) => Err(not_a_number("ceil", *b)),
+        }
+    }
+}
+
+fn not_a_number(function: &str, value: bool) -> EvalError {
+    EvalError::TypeError(format!("{} expects a number, got {}", function, value))
+}
+
+/// Integers are shown without a decimal point, integral floats keep `.0`
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Int(n) => write!(f, "{}", n),
+            Value::Float(v) => {
+                let formatted = format_result(*v);
+                if v.is_finite() && !formatted.contains(['.', 'e']) {
+                    write!(f, "{}.0", formatted)
+                } else {
+                    write!(f, "{}", formatted)
+                }
+            }
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+/// Parses the expression for `eval_value`, keeping numbers written with a decimal point or an
+/// exponent floats even when they are integral, such as `2.0`
+pub fn parse_value(expr: &str) -> Result<Node, ParseErr> {
+    Parser::from_tokenizer(Tokenizer::new(expr).float_literals(true))?.parse()
+}
+
+/// Evaluates the AST with integers and floats kept apart. Variables and constants are stored
+/// as floats, and integral ones read back as integers. Comparisons give booleans.
+pub fn eval_value(expr: &Node, ctx: &mut Context) -> Result<Value, EvalError> {
+    use self::Node::*;
+    use self::Value::*;
+    match expr {
+        Number(n) => Ok(Value::from_f64(*n * ctx.input_scale_factor())),
+        Variable(name) => ctx.lookup(name)
+            .map(Value::from_f64)
+            .ok_or_else(|| EvalError::UnboundVariable(name.clone())),
+        Add(expr1, expr2) => arithmetic(expr1, expr2, ctx, "+", i64::checked_add, |a, b| a + b),
+        Subtract(expr1, expr2) => arithmetic(expr1, expr2, ctx, "-", i64::checked_sub, |a, b| a - b),
+        Multiply(expr1, expr2) => arithmetic(expr1, expr2, ctx, "*", i64::checked_mul, |a, b| a * b),
+        Divide(expr1, expr2) => {
+            let left = eval_value(expr1, ctx)?.number()?;
+            Ok(Float(left / eval_value(expr2, ctx)?.number()?))
+        }
+        Caret(expr1, expr2) => {
+            let base = eval_value(expr1, ctx)?;
+            let exponent = ctx.unscaled(|ctx| eval_value(expr2, ctx))?;
+            match (base, exponent) {
+                (Int(b), Int(e)) if e >= 0 => {
+                    match checked_int_pow(b, e) {
+                        Some(n) => Ok(Int(n)),
+                        None if ctx.checked_integers_enabled() => {
+                            Err(EvalError::Overflow(format!("{}^{}", b, e)))
+                        }
+                        None => Ok(Float(ast::power(b as f64, e as f64)?)),
+                    }
+                }
+                _ => Ok(Float(ast::power(base.number()?, exponent.number()?)?)),
+            }
+        }
+        Negative(expr1) => match eval_value(expr1, ctx)? {
+            Int(n) => match n.checked_neg() {
+                Some(negated) => Ok(Int(negated)),
+                None if ctx.checked_integers_enabled() => Err(EvalError::Overflow(format!("-({})", n))),
+                None => Ok(Float(-(n as f64))),
+            },
+            Float(f) => Ok(Float(-f)),
+            Bool(b) => Err(EvalError::TypeError(format!("can not negate {}", b))),
+        },
+        Call(name, args) if args.len() == 1 && matches!(name.as_str(), "abs" | "sign" | "floor" | "ceil") => {
+            let value = eval_value(&args[0], ctx)?;
+            match name.as_str() {
+                "abs" => value.abs(),
+                "sign" => value.sign(),
+                "floor" => value.floor(),
+                _ => value.ceil(),
+            }
+        }
+        Call(name, args) if args.len() == 1 && name == "float" => {
+            Ok(Float(eval_value(&args[0], ctx)?.number()?))
+        }
+        Call(name, args) if args.len() == 1 && matches!(name.as_str(), "round" | "iround") => {
+            let func = ctx.get_func(name)
+                .cloned()
+                .ok_or_else(|| EvalError::UnknownFunction(name.clone()))?;
+            let arg = eval_value(&args[0], ctx)?.number()?;
+            let rounded = ctx.call_in_scope(name, &func, &[arg])?;
+            // Rounded floats outside of the exact range stay floats
+            Ok(match Value::from_f64(rounded) {
+                Int(n) => Int(n),
+                _ => Float(rounded),
+            })
+        }
+        Call(name, args) => {
+            let func = ctx.get_func(name)
+                .cloned()
+                .ok_or_else(|| EvalError::UnknownFunction(name.clone()))?;
+            let args = args.iter()
+                .map(|arg| eval_value(arg, ctx)?.number())
+                .collect::<Result<Vec<f64>, EvalError>>()?;
+            Ok(Float(ctx.call_in_scope(name, &func, &args)?))
+        }
+        Assign(name, expr1) => {
+            let value = eval_value(expr1, ctx)?;
+            ctx.set_var(name, value.to_f64())?;
+            Ok(value)
+        }
+        Equal(expr1, expr2) | NotEqual(expr1, expr2) => {
+            let left = eval_value(expr1, ctx)?;
+            let right = eval_value(expr2, ctx)?;
+            let equal = match (left, right) {
+                (Int(a), Int(b)) => a == b || ctx.approx_eq(a as f64, b as f64),
+                (Bool(a), Bool(b)) => a == b,
+                _ => ctx.approx_eq(left.number()?, right.number()?),
+            };
+            Ok(Bool(equal != matches!(expr, NotEqual(..))))
+        }
+        PercentChange(expr1, expr2) => {
+            let from = eval_value(expr1, ctx)?.number()?;
+            Ok(Float(ast::percent_change(from, eval_value(expr2, ctx)?.number()?)?))
+        }
+    }
+}
+
+/// Applies the integer operation when both operands are integers and it does not overflow,
+/// otherwise the float one. With checked integers an overflow fails instead.
+fn arithmetic(
+    expr1: &Node,
+    expr2: &Node,
+    ctx: &mut Context,
+    symbol: &str,
+    int: fn(i64, i64) -> Option<i64>,
+    float: fn(f64, f64) -> f64,
+) -> Result<Value, EvalError> {
+    let left = eval_value(expr1, ctx)?;
+    let right = eval_value(expr2, ctx)?;
+    match (left, right) {
+        (Value::Int(a), Value::Int(b)) => match int(a, b) {
+            Some(n) => Ok(Value::Int(n)),
+            None if ctx.checked_integers_enabled() => {
+                Err(EvalError::Overflow(format!("{} {} {}", a, symbol, b)))
+            }
+            None => Ok(Value::Float(float(a as f64, b as f64))),
+        },
+        _ => Ok(Value::Float(float(left.number()?, right.number()?))),
+    }
+}
+
+/// Raises the integer to a non negative power, or None if the result overflows i64.
+/// Powers of 0, 1 and -1 stay exact for exponents too large for `i64::checked_pow`.
+fn checked_int_pow(base: i64, exponent: i64) -> Option<i64> {
+    match base {
+        0 => Some(if exponent == 0 { 1 } else { 0 }),
+        1 => Some(1),
+        -1 => Some(if exponent % 2 == 0 { 1 } else { -1 }),
+        _ => base.checked_pow(u32::try_from(exponent).ok()?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn display(expr: &str) -> String {
+        eval_value(&parse_value(expr).unwrap(), &mut Context::new()).unwrap().to_string()
+    }
+
+    #[test]
+    fn integer_results_display_without_decimal_point() {
+        assert_eq!(display("2+2"), "4");
+        assert_eq!(display("2^10 - 3*4"), "1012");
+        assert_eq!(display("abs(-3)"), "3");
+        assert_eq!(display("iround(2.5)"), "2");
+        assert_eq!(display("round(2.5)"), "3");
+    }
+
+    #[test]
+    fn float_results_keep_decimals() {
+        assert_eq!(display("1/2"), "0.5");
+        assert_eq!(display("2.5*2"), "5.0");
+        assert_eq!(display("2.0 + 2"), "4.0");
+        assert_eq!(display("1e3"), "1000.0");
+        assert_eq!(display("4/2"), "2.0");
+        assert_eq!(display("sqrt(4)"), "2.0");
+        assert_eq!(display("float(2)"), "2.0");
+    }
+
+    #[test]
+    fn comparisons_give_booleans() {
+        assert_eq!(display("2 == 2.0"), "true");
+        assert!(matches!(eval_value(&parse_value("(1 == 1) + 1").unwrap(), &mut Context::new()),
+            Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn numeric_methods_keep_the_kind() {
+        assert_eq!(Value::Int(-3).abs(), Ok(Value::Int(3)));
+        assert_eq!(Value::Int(i64::MIN).abs(), Ok(Value::Float(9_223_372_036_854_775_808.0)));
+        assert_eq!(Value::Float(-2.5).abs(), Ok(Value::Float(2.5)));
+        assert_eq!(Value::Int(-7).sign(), Ok(Value::Int(-1)));
+        assert_eq!(Value::Float(-0.0).sign(), Ok(Value::Float(0.0)));
+        assert_eq!(Value::Float(0.5).sign(), Ok(Value::Float(1.0)));
+        assert_eq!(Value::Int(4).floor(), Ok(Value::Int(4)));
+        assert_eq!(Value::Float(-2.5).floor(), Ok(Value::Float(-3.0)));
+        assert_eq!(Value::Float(2.1).ceil(), Ok(Value::Float(3.0)));
+    }
+
+    #[test]
+    fn numeric_methods_reject_booleans() {
+        assert_eq!(Value::Bool(true).abs(), Err(EvalError::TypeError("abs expects a number, got true".into())));
+        assert_eq!(Value::Bool(false).sign(), Err(EvalError::TypeError("sign expects a number, got false".into())));
+        assert!(matches!(Value::Bool(true).floor(), Err(EvalError::TypeError(_))));
+        assert!(matches!(Value::Bool(true).ceil(), Err(EvalError::TypeError(_))));
+        assert!(matches!(Value::Bool(true).number(), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn integer_powers_are_exact_until_they_overflow() {
+        let checked = |expr: &str| {
+            eval_value(&parse_value(expr).unwrap(), &mut Context::new().checked_integers(true))
+        };
+        assert_eq!(checked("2^62"), Ok(Value::Int(4_611_686_018_427_387_904)));
+        assert_eq!(checked("2^63"), Err(EvalError::Overflow("2^63".into())));
+        assert_eq!(checked("2^-1"), Ok(Value::Float(0.5)));
+        assert!(matches!(checked("2^62 * 2"), Err(EvalError::Overflow(_))));
+        assert_eq!(checked("2^5000000000"), Err(EvalError::Overflow("2^5000000000".into())));
+
+        // Powers of 0, 1 and -1 never overflow, however large the exponent
+        assert_eq!(checked("1^5000000000"), Ok(Value::Int(1)));
+        assert_eq!(checked("0^5000000000"), Ok(Value::Int(0)));
+        assert_eq!(checked("0^0"), Ok(Value::Int(1)));
+        assert_eq!(checked("(-1)^5000000000"), Ok(Value::Int(1)));
+        assert_eq!(checked("(-1)^5000000001"), Ok(Value::Int(-1)));
+
+        // Unchecked integers continue as floats
+        assert_eq!(display("2^63"), "9223372036854776000.0");
+        assert_eq!(display("2^-1"), "0.5");
+    }
+}
@@ -0,0 +1,150 @@
+//! Runs the rcalc binary and checks its output.
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+fn rcalc(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rcalc"))
+        .args(args)
+        .env_remove("RCALC_PRECISION")
+        .output()
+        .expect("rcalc runs")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+/// Path in the temporary directory unique to this test, removed when dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str) -> Self {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let count = COUNT.fetch_add(1, Ordering::Relaxed);
+        TempFile(env::temp_dir().join(format!("rcalc-{}-{}-{}", process::id(), count, name)))
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    fn path_str(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Writes the contents to a new temporary file
+fn temp_file(name: &str, contents: &str) -> TempFile {
+    let file = TempFile::new(name);
+    fs::write(file.path(), contents).expect("temp file is writable");
+    file
+}
+
+#[test]
+fn ast_is_printed_before_the_result() {
+    let output = rcalc(&["--ast", "1 + 2*3"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "\
+Add
+├── Number 1
+└── Multiply
+    ├── Number 2
+    └── Number 3
+7
+");
+}
+
+#[test]
+fn file_lines_are_evaluated_and_errors_numbered() {
+    let file = temp_file("lines.txt", "1 + 1\n\n# a comment\nx = 3 * 2\n1 +\nx / 4 # halved\n");
+    let output = rcalc(&["--file", file.path_str()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stdout(&output), "2\n6\n1.5\n");
+    assert!(stderr(&output).starts_with("Error on line 5: "), "{}", stderr(&output));
+}
+
+#[test]
+fn missing_file_is_an_error() {
+    let output = rcalc(&["--file", "/nonexistent/rcalc-lines.txt"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).starts_with("Error: can not read /nonexistent/rcalc-lines.txt"));
+}
+
+#[test]
+fn table_prints_the_variable_and_the_result() {
+    let output = rcalc(&["--table", "x^2", "--var", "x", "--from", "0", "--to", "5", "--step", "1"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "x  x^2\n0  0\n1  1\n2  4\n3  9\n4  16\n5  25\n");
+}
+
+#[test]
+fn table_shows_errors_in_their_row() {
+    let output = rcalc(&["--table", "ln(x)", "--from", "0", "--to", "1"]);
+    assert!(output.status.success());
+    assert_eq!(stdout(&output), "x  ln(x)\n0  Error: ln domain error: argument 0 must be positive\n1  0\n");
+
+    let output = rcalc(&["--table", "x", "--from", "0"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("--table needs --from and --to"), "{}", stderr(&output));
+
+    let output = rcalc(&["--table", "x", "--from", "0", "--to", "1e12"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("at most 10000 are allowed"), "{}", stderr(&output));
+}
+
+#[test]
+fn definitions_file_extends_the_calculator() {
+    let file = temp_file("defs.txt", "g = 9.81\ndouble(x) = x*2\n");
+    let output = rcalc(&["--defs", file.path_str(), "double(g)"]);
+    assert_eq!(stdout(&output), "19.62\n");
+
+    let file = temp_file("bad-defs.txt", "g = 9.81\ndouble(x) = \n");
+    let output = rcalc(&["--defs", file.path_str(), "1"]);
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("Line 2: "), "{}", stderr(&output));
+}
+
+/// Runs the REPL with the input lines
+#[cfg(feature = "session")]
+fn repl(args: &[&str], input: &str) -> Output {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rcalc"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("rcalc runs");
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[cfg(feature = "session")]
+#[test]
+fn saved_variables_are_restored_after_restart() {
+    let file = TempFile::new("session.json");
+    let path_str = file.path_str();
+    let saved = repl(&[], &format!("x = 2\nrate = 0.5\n:save {}\n", path_str));
+    assert!(stdout(&saved).contains(&format!("Saved variables to {}", path_str)));
+
+    let loaded = repl(&[], &format!(":load {}\nx + rate\n", path_str));
+    assert!(stdout(&loaded).contains("Loaded 2 variable(s)"));
+    assert!(stdout(&loaded).contains("The computed number is 2.5"));
+
+    let output = rcalc(&["--load", path_str, "x * rate"]);
+    assert_eq!(stdout(&output), "1\n");
+}
@@ -0,0 +1,99 @@
+//! Checks the `tracing` events of parsing and evaluating with a collecting subscriber.
+#![cfg(feature = "tracing")]
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use rcalc::parsemath::ast;
+use rcalc::parsemath::context::Context;
+use rcalc::parsemath::parser::Parser;
+
+/// Fields of an event by name, including its `message`
+type Fields = HashMap<String, String>;
+
+/// Records the names of created spans and the fields of every event
+#[derive(Clone, Default)]
+struct Collector {
+    spans: Arc<Mutex<Vec<String>>>,
+    events: Arc<Mutex<Vec<Fields>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Collector {
+    fn events(&self, message: &str) -> Vec<Fields> {
+        self.events.lock().unwrap().iter()
+            .filter(|fields| fields["message"] == message)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Visitor adding the fields of an event
+struct Recorder<'a>(&'a mut Fields);
+
+impl Visit for Recorder<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name().to_string(), format!("{:?}", value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+    }
+}
+
+impl Subscriber for Collector {
+    fn enabled(&self, _: &Metadata) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes) -> Id {
+        self.spans.lock().unwrap().push(span.metadata().name().to_string());
+        Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event) {
+        let mut fields = Fields::new();
+        event.record(&mut Recorder(&mut fields));
+        self.events.lock().unwrap().push(fields);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn parsing_and_evaluation_emit_events() {
+    let collector = Collector::default();
+    let value = tracing::subscriber::with_default(collector.clone(), || {
+        let node = Parser::new("1 + 2").unwrap().parse().unwrap();
+        ast::eval_with(&node, &mut Context::new()).unwrap()
+    });
+    assert_eq!(value, 3.0);
+
+    let tokens: Vec<String> = collector.events("token").iter().map(|fields| fields["token"].clone()).collect();
+    assert!(tokens.starts_with(&["Some(Num(1.0))".into(), "Some(Add)".into(), "Some(Num(2.0))".into()]), "{:?}", tokens);
+
+    let parsed = collector.events("parsed");
+    assert_eq!(parsed.last().unwrap()["node"], "1 + 2");
+    assert!(parsed.iter().all(|fields| fields.contains_key("elapsed_ns")));
+
+    let evaluated = collector.events("evaluated");
+    let results: Vec<(&str, &str)> = evaluated.iter()
+        .map(|fields| (fields["node"].as_str(), fields["result"].as_str()))
+        .collect();
+    assert_eq!(results, vec![("1", "Ok(1.0)"), ("2", "Ok(2.0)"), ("1 + 2", "Ok(3.0)")]);
+
+    let spans = collector.spans.lock().unwrap();
+    assert!(spans.contains(&"parse".to_string()));
+    assert_eq!(spans.iter().filter(|name| *name == "eval").count(), 3);
+}